@@ -0,0 +1,13 @@
+use std::io;
+use std::process;
+
+// the language server for editors, speaking LSP over stdin and stdout
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+
+    if let Err(e) = hack_assembler::run_language_server(stdin.lock(), stdout.lock()) {
+        eprintln!("hack_lsp: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,253 @@
+use std::fmt;
+
+// mnemonic <-> bits tables shared by the assembler and the disassembler.
+// the tables hold the canonical spellings; `parse` also takes the others.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Jump {
+  Null,
+  Jgt,
+  Jeq,
+  Jge,
+  Jlt,
+  Jne,
+  Jle,
+  Jmp,
+}
+
+const JUMP_TABLE: [(Jump, &str, u16); 8] = [
+  (Jump::Null, ""   , 0b000),
+  (Jump::Jgt , "JGT", 0b001),
+  (Jump::Jeq , "JEQ", 0b010),
+  (Jump::Jge , "JGE", 0b011),
+  (Jump::Jlt , "JLT", 0b100),
+  (Jump::Jne , "JNE", 0b101),
+  (Jump::Jle , "JLE", 0b110),
+  (Jump::Jmp , "JMP", 0b111),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Comp {
+  Zero,
+  One,
+  NegOne,
+  D,
+  A,
+  M,
+  NotD,
+  NotA,
+  NotM,
+  NegD,
+  NegA,
+  NegM,
+  DPlusOne,
+  APlusOne,
+  MPlusOne,
+  DMinusOne,
+  AMinusOne,
+  MMinusOne,
+  DPlusA,
+  DPlusM,
+  DMinusA,
+  DMinusM,
+  AMinusD,
+  MMinusD,
+  DAndA,
+  DAndM,
+  DOrA,
+  DOrM,
+  // an undocumented setting of the a bit and the six ALU control bits
+  Raw(u16),
+}
+
+// the a bit followed by c1..c6
+const COMP_TABLE: [(Comp, &str, u16); 28] = [
+  (Comp::Zero     , "0"  , 0b0101010),
+  (Comp::One      , "1"  , 0b0111111),
+  (Comp::NegOne   , "-1" , 0b0111010),
+  (Comp::D        , "D"  , 0b0001100),
+  (Comp::A        , "A"  , 0b0110000),
+  (Comp::M        , "M"  , 0b1110000),
+  (Comp::NotD     , "!D" , 0b0001101),
+  (Comp::NotA     , "!A" , 0b0110001),
+  (Comp::NotM     , "!M" , 0b1110001),
+  (Comp::NegD     , "-D" , 0b0001111),
+  (Comp::NegA     , "-A" , 0b0110011),
+  (Comp::NegM     , "-M" , 0b1110011),
+  (Comp::DPlusOne , "D+1", 0b0011111),
+  (Comp::APlusOne , "A+1", 0b0110111),
+  (Comp::MPlusOne , "M+1", 0b1110111),
+  (Comp::DMinusOne, "D-1", 0b0001110),
+  (Comp::AMinusOne, "A-1", 0b0110010),
+  (Comp::MMinusOne, "M-1", 0b1110010),
+  (Comp::DPlusA   , "D+A", 0b0000010),
+  (Comp::DPlusM   , "D+M", 0b1000010),
+  (Comp::DMinusA  , "D-A", 0b0010011),
+  (Comp::DMinusM  , "D-M", 0b1010011),
+  (Comp::AMinusD  , "A-D", 0b0000111),
+  (Comp::MMinusD  , "M-D", 0b1000111),
+  (Comp::DAndA    , "D&A", 0b0000000),
+  (Comp::DAndM    , "D&M", 0b1000000),
+  (Comp::DOrA     , "D|A", 0b0010101),
+  (Comp::DOrM     , "D|M", 0b1010101),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dest {
+  Null,
+  M,
+  D,
+  Md,
+  A,
+  Am,
+  Ad,
+  Amd,
+}
+
+const DEST_TABLE: [(Dest, &str, u16); 8] = [
+  (Dest::Null, ""   , 0b000),
+  (Dest::M   , "M"  , 0b001),
+  (Dest::D   , "D"  , 0b010),
+  (Dest::Md  , "MD" , 0b011),
+  (Dest::A   , "A"  , 0b100),
+  (Dest::Am  , "AM" , 0b101),
+  (Dest::Ad  , "AD" , 0b110),
+  (Dest::Amd , "AMD", 0b111),
+];
+
+fn by_mnemonic<T: Copy>(table: &[(T, &str, u16)], mnemonic: &str) -> Option<T> {
+  table.iter().find(|(_, m, _)| *m == mnemonic).map(|(field, _, _)| *field)
+}
+
+fn by_bits<T: Copy>(table: &[(T, &str, u16)], bits: u16) -> Option<T> {
+  table.iter().find(|(_, _, b)| *b == bits).map(|(field, _, _)| *field)
+}
+
+fn entry<T: Copy + PartialEq>(table: &[(T, &'static str, u16)], field: T) -> (&'static str, u16) {
+  table.iter()
+    .find(|(f, _, _)| *f == field)
+    .map(|(_, mnemonic, bits)| (*mnemonic, *bits))
+    .unwrap()
+}
+
+impl Jump {
+  pub fn parse(mnemonic: &str) -> Option<Jump> {
+    by_mnemonic(&JUMP_TABLE, mnemonic)
+  }
+
+  pub fn from_bits(bits: u16) -> Jump {
+    by_bits(&JUMP_TABLE, bits & 0b111).unwrap()
+  }
+
+  pub fn bits(self) -> u16 {
+    entry(&JUMP_TABLE, self).1
+  }
+
+  pub fn mnemonic(self) -> &'static str {
+    entry(&JUMP_TABLE, self).0
+  }
+}
+
+impl Comp {
+  // `%abbbbbb` gives the a bit and zx nx zy ny f no directly; `+`, `&` and `|`
+  // take their operands in either order
+  pub fn parse(mnemonic: &str) -> Option<Comp> {
+    if let Some(comp) = by_mnemonic(&COMP_TABLE, mnemonic) {
+      return Some(comp);
+    }
+
+    if let Some(bits) = mnemonic.strip_prefix('%') {
+      if bits.len() != 7 || !bits.chars().all(|c| c == '0' || c == '1') {
+        return None;
+      }
+
+      let bits = u16::from_str_radix(bits, 2).ok()?;
+      return Some(Comp::from_bits(bits).unwrap_or(Comp::Raw(bits)));
+    }
+
+    let op = mnemonic.find(|c| "+&|".contains(c)).filter(|&op| op > 0)?;
+    let swapped = format!("{}{}{}", &mnemonic[op + 1..], &mnemonic[op..op + 1], &mnemonic[..op]);
+    by_mnemonic(&COMP_TABLE, &swapped)
+  }
+
+  // None for the ALU settings the course does not document
+  pub fn from_bits(bits: u16) -> Option<Comp> {
+    by_bits(&COMP_TABLE, bits & 0b1111111)
+  }
+
+  pub fn bits(self) -> u16 {
+    match self {
+      Comp::Raw(bits) => bits,
+      _               => entry(&COMP_TABLE, self).1,
+    }
+  }
+
+  // None for raw bits, which have no mnemonic
+  pub fn mnemonic(self) -> Option<&'static str> {
+    match self {
+      Comp::Raw(_) => None,
+      _            => Some(entry(&COMP_TABLE, self).0),
+    }
+  }
+}
+
+impl Dest {
+  // the registers may be listed in any order, each at most once
+  pub fn parse(mnemonic: &str) -> Option<Dest> {
+    let mut bits = 0;
+
+    for c in mnemonic.chars() {
+      let bit = match c {
+        'A' => 0b100,
+        'D' => 0b010,
+        'M' => 0b001,
+        _   => return None,
+      };
+
+      if bits & bit != 0 {
+        return None;
+      }
+      bits |= bit;
+    }
+
+    by_bits(&DEST_TABLE, bits)
+  }
+
+  pub fn from_bits(bits: u16) -> Dest {
+    by_bits(&DEST_TABLE, bits & 0b111).unwrap()
+  }
+
+  pub fn bits(self) -> u16 {
+    entry(&DEST_TABLE, self).1
+  }
+
+  pub fn mnemonic(self) -> &'static str {
+    entry(&DEST_TABLE, self).0
+  }
+}
+
+impl fmt::Display for Jump {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.pad(self.mnemonic())
+  }
+}
+
+impl fmt::Display for Comp {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.mnemonic() {
+      Some(mnemonic) => f.pad(mnemonic),
+      None           => f.pad(&format!("%{:07b}", self.bits())),
+    }
+  }
+}
+
+impl fmt::Display for Dest {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.pad(self.mnemonic())
+  }
+}
+
+// 111a cccc ccdd djjj
+pub fn encode_c(dest: Dest, comp: Comp, jump: Jump) -> u16 {
+  0b111 << 13 | comp.bits() << 6 | dest.bits() << 3 | jump.bits()
+}
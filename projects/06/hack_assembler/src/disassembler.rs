@@ -0,0 +1,133 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::code::{Comp, Dest, Jump};
+use crate::error::{AssembleError, Diagnostics, ErrorKind, Span};
+
+// label and variable names read back from a symbol file, keyed by address
+#[derive(Default)]
+pub struct Symbols {
+  labels: BTreeMap<i32, Vec<String>>,
+  variables: HashMap<i32, String>,
+}
+
+impl Symbols {
+  // each entry is `<kind> <address> <name>`, where kind is `label`, `variable`,
+  // `data`, `predefined` or `defined`; the last two and any further columns are ignored
+  pub fn parse(input: &str, diagnostics: &mut Diagnostics) -> Symbols {
+    let mut symbols = Symbols::default();
+
+    for (index, line) in input.split('\n').enumerate() {
+      let entry = match line.find("//") {
+        Some(comment_index) => &line[..comment_index],
+        None                => line,
+      };
+
+      let fields: Vec<&str> = entry.split_whitespace().collect();
+      if fields.is_empty() {
+        continue;
+      }
+
+      let address = fields.get(1).and_then(|address| address.parse::<i32>().ok());
+      match (fields[0], address, fields.get(2)) {
+        ("label", Some(address), Some(name))    => {
+          symbols.labels.entry(address).or_default().push(String::from(*name));
+        },
+        ("variable", Some(address), Some(name))
+        | ("data", Some(address), Some(name))   => {
+          symbols.variables.entry(address).or_insert_with(|| String::from(*name));
+        },
+        ("predefined", Some(_), Some(_))
+        | ("defined", Some(_), Some(_))         => (),
+        _                                       => {
+          let text = entry.trim();
+          let indent = entry.len() - entry.trim_start().len();
+          let span = Span::new(index + 1, entry[..indent].chars().count() + 1, text.chars().count());
+          let kind = ErrorKind::MalformedSymbol(String::from(text));
+          diagnostics.push(AssembleError::new(kind, span));
+        },
+      }
+    }
+
+    symbols
+  }
+}
+
+// turn each 16-bit word back into an A- or C-instruction; `locations` says
+// where each word was read from, as `read_machine_code` gives them
+pub fn disassemble(words: &[u16], locations: &[Span], symbols: &Symbols, diagnostics: &mut Diagnostics) -> String {
+  let mut output = String::new();
+
+  for (address, &word) in words.iter().enumerate() {
+    if diagnostics.is_full() {
+      break;
+    }
+
+    if let Some(labels) = symbols.labels.get(&(address as i32)) {
+      labels.iter().for_each(|label| output.push_str(&format!("({})\n", label)));
+    }
+
+    // bits can only be pointed at in a word written out in binary
+    let location = locations[address];
+    let bits = |offset, len| if location.len == 16 { location.sub(offset, len) } else { location };
+
+    let instruction = if word & 0x8000 == 0 {
+      let value = i32::from(word);
+
+      // a jump right after the load means the value is a ROM address
+      let jumps = match words.get(address + 1) {
+        Some(next) => next & 0x8000 != 0 && Jump::from_bits(*next) != Jump::Null,
+        None       => false,
+      };
+
+      let name = if jumps {
+        symbols.labels.get(&value).and_then(|labels| labels.first())
+      } else {
+        symbols.variables.get(&value)
+      };
+
+      match name {
+        Some(name) => format!("@{}", name),
+        None       => format!("@{}", value),
+      }
+    } else {
+      if word & 0x6000 != 0x6000 {
+        let kind = ErrorKind::UnusedBits(format!("{:016b}", word));
+        diagnostics.push(AssembleError::new(kind, bits(1, 2)));
+      }
+
+      let comp_bits = word >> 6 & 0b1111111;
+      if Comp::from_bits(comp_bits).is_none() {
+        let kind = ErrorKind::UndocumentedComp(format!("{:07b}", comp_bits));
+        diagnostics.push(AssembleError::new(kind, bits(3, 7)));
+      }
+
+      c_instruction(word)
+    };
+
+    output.push_str(&instruction);
+    output.push('\n');
+  }
+
+  // labels can also point just past the last instruction
+  if let Some(labels) = symbols.labels.get(&(words.len() as i32)) {
+    labels.iter().for_each(|label| output.push_str(&format!("({})\n", label)));
+  }
+
+  output
+}
+
+// the mnemonic of a C-instruction word; an undocumented comp is written as
+// raw `%bits`, which assemble back with --raw-alu
+pub fn c_instruction(word: u16) -> String {
+  let comp_bits = word >> 6 & 0b1111111;
+  let comp = Comp::from_bits(comp_bits).unwrap_or(Comp::Raw(comp_bits));
+  let dest = Dest::from_bits(word >> 3);
+  let jump = Jump::from_bits(word);
+
+  match (dest, jump) {
+    (Dest::Null, Jump::Null) => comp.to_string(),
+    (Dest::Null, _)          => format!("{};{}", comp, jump),
+    (_, Jump::Null)          => format!("{}={}", dest, comp),
+    (_, _)                   => format!("{}={};{}", dest, comp, jump),
+  }
+}
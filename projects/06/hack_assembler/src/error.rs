@@ -0,0 +1,465 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::lexer::lines;
+use crate::source::Sources;
+
+/// The 1-based line and column of the offending text, with its length in
+/// chars. `file` indexes the [`Sources`] of an assembly; 0 is the main input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+  pub file: u32,
+  pub line: usize,
+  pub column: usize,
+  pub len: usize,
+  /// Set for code produced by a macro, whose text is not in the source.
+  pub expanded: bool,
+}
+
+impl Span {
+  pub fn new(line: usize, column: usize, len: usize) -> Span {
+    Span { file: 0, line, column, len, expanded: false }
+  }
+
+  /// Narrow a span down to `len` chars starting `offset` chars into it.
+  /// Expanded code can only be pointed at as a whole.
+  pub fn sub(&self, offset: usize, len: usize) -> Span {
+    if self.expanded {
+      return *self;
+    }
+
+    Span { column: self.column + offset, len, ..*self }
+  }
+
+  pub fn in_file(self, file: u32) -> Span {
+    Span { file, ..self }
+  }
+
+  pub fn expanded(self) -> Span {
+    Span { expanded: true, ..self }
+  }
+}
+
+/// Whether a diagnostic stops assembly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Severity::Error   => write!(f, "error"),
+      Severity::Warning => write!(f, "warning"),
+    }
+  }
+}
+
+/// What a diagnostic is about, with the text or values its message quotes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+  UnknownComp(String),
+  UnknownDest(String),
+  UnknownJump(String),
+  RawAluDisabled(String),
+  MalformedLabel(String),
+  TextAfterLabel(String),
+  BadLiteral(String, String),
+  LiteralOutOfRange(String),
+  ExpressionOutOfRange(String, i64),
+  LabelOutOfRange(String, u16),
+  BadExpression(String),
+  UndefinedInExpression(String),
+  MissingOperand,
+  UnknownDirective(String),
+  MalformedInclude(String),
+  IncludeNotFound(String),
+  IncludeFailed(String),
+  IncludeCycle(String),
+  MalformedConditional(String),
+  MalformedData(String),
+  WordOutOfRange(String, i64),
+  MalformedPseudo(String),
+  DuplicateSymbol(String, Option<usize>),
+  UndefinedInCondition(String),
+  MalformedMacro(String),
+  DuplicateMacro(String, usize),
+  MacroArity(String, usize, usize),
+  UnknownMacroParam(String),
+  MacroRecursion(String),
+  DuplicateLabel(String, usize),
+  LabelIsDefined(String),
+  UndefinedNumericLabel(u32, bool),
+  /// `SCOPE.name`, or just `.name` outside any scope.
+  UndefinedLocalLabel(String),
+  MalformedLinkage(String),
+  NotInObject(String),
+  NotRelocatable(String),
+  ExportNotLabel(String),
+  UndefinedExtern(String),
+  UnresolvedExtern(String),
+  DuplicateExport(String),
+  VariableIsExported(String),
+  MalformedObject(String),
+  RomOverflow(usize),
+  RamOverflow(String, u16),
+  StackOverlap(String, u16),
+  SuspiciousVariable(String, Suspicion),
+  NoEffect(String),
+  MalformedWord(String),
+  MalformedMachineCode(String),
+  MalformedSymbol(String),
+  UndocumentedComp(String),
+  UnusedBits(String),
+}
+
+/// Why a variable looks like a typo.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Suspicion {
+  JumpTarget,
+  NearLabel,
+  UsedOnce,
+}
+
+impl ErrorKind {
+  pub fn severity(&self) -> Severity {
+    match self {
+      ErrorKind::NoEffect(_)
+      | ErrorKind::StackOverlap(_, _)
+      | ErrorKind::SuspiciousVariable(_, _)
+      | ErrorKind::UndocumentedComp(_)
+      | ErrorKind::UnusedBits(_) => Severity::Warning,
+      _                          => Severity::Error,
+    }
+  }
+}
+
+impl fmt::Display for ErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ErrorKind::UnknownComp(comp) if comp.is_empty() => write!(f, "missing comp field"),
+      ErrorKind::UnknownComp(comp)   => write!(f, "unknown comp `{}`", comp),
+      ErrorKind::UnknownDest(dest)   => write!(f, "unknown dest `{}`", dest),
+      ErrorKind::UnknownJump(jmp)    => write!(f, "unknown jump `{}`", jmp),
+      ErrorKind::RawAluDisabled(comp) => write!(f, "raw ALU bits `{}` need --raw-alu", comp),
+      ErrorKind::MalformedLabel(lbl) => write!(f, "malformed label `{}`", lbl),
+      ErrorKind::TextAfterLabel(text) => write!(f, "unexpected `{}` after the label", text),
+      ErrorKind::BadLiteral(lit, why) => write!(f, "bad literal `{}`: {}", lit, why),
+      ErrorKind::LiteralOutOfRange(lit) =>
+        write!(f, "`{}` is out of range, A-instructions take values from 0 to 32767", lit),
+      ErrorKind::ExpressionOutOfRange(expr, value) =>
+        write!(f, "`{}` evaluates to {}, out of the A-instruction range 0 to 32767", expr, value),
+      ErrorKind::LabelOutOfRange(label, address) =>
+        write!(f, "`{}` is at ROM address {}, out of the A-instruction range 0 to 32767", label, address),
+      ErrorKind::BadExpression(why)  => write!(f, "bad expression: {}", why),
+      ErrorKind::UndefinedInExpression(name) =>
+        write!(f, "`{}` has no address yet; expressions can only use labels and variables allocated earlier", name),
+      ErrorKind::UnknownDirective(name) => write!(f, "unknown directive `{}`", name),
+      ErrorKind::MalformedInclude(why) => write!(f, "malformed include: {}", why),
+      ErrorKind::IncludeNotFound(path) =>
+        write!(f, "cannot find `{}` next to this file or on the include path", path),
+      ErrorKind::IncludeFailed(why)  => write!(f, "cannot read included file: {}", why),
+      ErrorKind::IncludeCycle(chain) => write!(f, "include cycle: {}", chain),
+      ErrorKind::MalformedConditional(why) => write!(f, "malformed conditional: {}", why),
+      ErrorKind::UndefinedInCondition(name) =>
+        write!(f, "`{}` is not defined; conditions can only use symbols given with -D", name),
+      ErrorKind::MalformedData(why)  => write!(f, "malformed data directive: {}", why),
+      ErrorKind::WordOutOfRange(expr, value) =>
+        write!(f, "`{}` evaluates to {}, which does not fit in a 16-bit word", expr, value),
+      ErrorKind::MalformedPseudo(usage) => write!(f, "malformed pseudo-instruction, expected `{}`", usage),
+      ErrorKind::DuplicateSymbol(name, Some(first)) =>
+        write!(f, "`{}` is already defined on line {}", name, first),
+      ErrorKind::DuplicateSymbol(name, None) =>
+        write!(f, "`{}` is already a predefined or -D symbol", name),
+      ErrorKind::MalformedMacro(why) => write!(f, "malformed macro: {}", why),
+      ErrorKind::DuplicateMacro(name, first) =>
+        write!(f, "macro `{}` is already defined on line {}", name, first),
+      ErrorKind::MacroArity(name, expected, found) =>
+        write!(f, "macro `{}` takes {} argument(s) but {} were given", name, expected, found),
+      ErrorKind::UnknownMacroParam(name) => write!(f, "`\\{}` is not a parameter of this macro", name),
+      ErrorKind::MacroRecursion(name) =>
+        write!(f, "macro `{}` expands too deeply, is it recursive?", name),
+      ErrorKind::MissingOperand      => write!(f, "missing A-instruction operand"),
+      ErrorKind::DuplicateLabel(name, first) =>
+        write!(f, "label `{}` is already defined on line {}", name, first),
+      ErrorKind::LabelIsDefined(name) => write!(f, "label `{}` is also defined with -D", name),
+      ErrorKind::UndefinedNumericLabel(label, true) => write!(f, "no `{}:` label after `@{}f`", label, label),
+      ErrorKind::UndefinedNumericLabel(label, false) => write!(f, "no `{}:` label before `@{}b`", label, label),
+      ErrorKind::UndefinedLocalLabel(label) => match label.find('.') {
+        Some(dot) if dot > 0 => write!(f, "no local label `{}` under `{}`", &label[dot..], &label[..dot]),
+        _                    => write!(f, "no local label `{}`, and there is no global label before it to scope one", label),
+      },
+      ErrorKind::MalformedLinkage(directive) => write!(f, "malformed `{0}`, expected `{0} NAME`", directive),
+      ErrorKind::NotInObject(name) =>
+        write!(f, "data block `{}` needs startup code, which object files do not have", name),
+      ErrorKind::NotRelocatable(expr) =>
+        write!(f, "`{}` cannot be relocated; object files only allow a label, extern or variable plus or minus a constant", expr),
+      ErrorKind::ExportNotLabel(name) => write!(f, "`{}` is exported but is not a label in this file", name),
+      ErrorKind::UndefinedExtern(name) =>
+        write!(f, "`{}` is declared `.extern` but no label defines it; assemble with --object to link it later", name),
+      ErrorKind::UnresolvedExtern(name) => write!(f, "`{}` is not exported by any object file", name),
+      ErrorKind::DuplicateExport(name) => write!(f, "`{}` is exported by more than one object file", name),
+      ErrorKind::VariableIsExported(name) =>
+        write!(f, "`{}` is used as a variable but another object file exports it; declare it `.extern`", name),
+      ErrorKind::MalformedObject(why) => write!(f, "malformed object file: {}", why),
+      ErrorKind::RomOverflow(words) =>
+        write!(f, "the program takes {} words but ROM only holds 32768, starting with this one", words),
+      ErrorKind::RamOverflow(name, limit) =>
+        write!(f, "no RAM left for `{}`, variables and data may only go up to address {}", name, limit),
+      ErrorKind::StackOverlap(name, address) =>
+        write!(f, "`{}` is at RAM {}, where VM translator code keeps its stack (256 to 2047)", name, address),
+      ErrorKind::SuspiciousVariable(name, Suspicion::JumpTarget) =>
+        write!(f, "`{}` is jumped to but is not a label, so it became a variable", name),
+      ErrorKind::SuspiciousVariable(name, Suspicion::NearLabel) =>
+        write!(f, "variable `{}` looks like a misspelt label", name),
+      ErrorKind::SuspiciousVariable(name, Suspicion::UsedOnce) =>
+        write!(f, "variable `{}` is only used once", name),
+      ErrorKind::NoEffect(instr)     => write!(f, "`{}` has neither dest nor jump and does nothing", instr),
+      ErrorKind::MalformedWord(word) => write!(f, "`{}` is not a 16-bit binary word", word),
+      ErrorKind::MalformedMachineCode(why) => write!(f, "cannot read machine code: {}", why),
+      ErrorKind::MalformedSymbol(entry) =>
+        write!(f, "malformed symbol entry `{}`, expected `<kind> <address> <name>`", entry),
+      ErrorKind::UndocumentedComp(bits) => write!(f, "comp bits `{}` are not a documented ALU operation", bits),
+      ErrorKind::UnusedBits(word)    => write!(f, "C-instruction `{}` does not set its two unused bits", word),
+    }
+  }
+}
+
+/// One error or warning, pointing at the code it is about.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssembleError {
+  pub kind: ErrorKind,
+  pub severity: Severity,
+  pub span: Span,
+  notes: Vec<String>,
+  source: Option<Box<SourceContext>>,
+}
+
+// what is needed to render a diagnostic without the original source at hand
+#[derive(Debug, Clone, PartialEq)]
+struct SourceContext {
+  file: String,
+  line: Option<String>,
+}
+
+impl AssembleError {
+  pub fn new(kind: ErrorKind, span: Span) -> AssembleError {
+    AssembleError {
+      severity: kind.severity(),
+      kind,
+      span,
+      notes: Vec::new(),
+      source: None,
+    }
+  }
+
+  pub fn with_note(mut self, note: String) -> AssembleError {
+    self.notes.push(note);
+    self
+  }
+
+  pub fn notes(&self) -> &[String] {
+    &self.notes
+  }
+
+  /// Attach the file name and the offending line, so the error can be
+  /// rendered on its own.
+  pub fn with_source(mut self, file: &str, source: &str) -> AssembleError {
+    let line = lines(source).get(self.span.line - 1).map(|line| String::from(*line));
+
+    self.source = Some(Box::new(SourceContext {
+      file: String::from(file),
+      line,
+    }));
+    self
+  }
+
+  /// Attach only the file name, for input without lines to quote.
+  pub fn with_file(mut self, file: &str) -> AssembleError {
+    self.source = Some(Box::new(SourceContext {
+      file: String::from(file),
+      line: None,
+    }));
+    self
+  }
+
+  /// Like [`with_source`](AssembleError::with_source), picking the file the
+  /// span points into and noting how it was included.
+  pub fn with_sources(self, sources: &Sources) -> AssembleError {
+    let file = self.span.file;
+    let mut diagnostic = match sources.get(file) {
+      Some(source) => self.with_source(&source.name, &source.text),
+      None         => self,
+    };
+
+    for span in sources.include_chain(file) {
+      let name = sources.get(span.file).map_or("<input>", |source| source.name.as_str());
+      diagnostic = diagnostic.with_note(format!("included from {}:{}", name, span.line));
+    }
+
+    diagnostic
+  }
+}
+
+impl fmt::Display for AssembleError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "{}: {}", self.severity, self.kind)?;
+
+    let file = self.source.as_ref().map_or("<input>", |source| source.file.as_str());
+    write!(f, " --> {}:{}:{}", file, self.span.line, self.span.column)?;
+
+    let mut gutter = String::new();
+    if let Some(line) = self.source.as_ref().and_then(|source| source.line.as_ref()) {
+      let number = self.span.line.to_string();
+      gutter = " ".repeat(number.len());
+
+      // keep tabs so the caret lines up with the text above it
+      let padding: String = line
+        .chars()
+        .take(self.span.column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+      write!(f, "\n{} |\n{} | {}\n{} | {}{}",
+        gutter,
+        number, line,
+        gutter, padding, "^".repeat(self.span.len.max(1)))?;
+    }
+
+    for note in &self.notes {
+      write!(f, "\n{} = note: {}", gutter, note)?;
+    }
+
+    Ok(())
+  }
+}
+
+impl Error for AssembleError {}
+
+/// Every diagnostic found in one pass over a file, up to `error_limit` errors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostics {
+  diagnostics: Vec<AssembleError>,
+  error_limit: usize,
+}
+
+impl Diagnostics {
+  /// An `error_limit` of 0 means no limit.
+  pub fn new(error_limit: usize) -> Diagnostics {
+    Diagnostics {
+      diagnostics: Vec::new(),
+      error_limit,
+    }
+  }
+
+  pub fn push(&mut self, diagnostic: AssembleError) {
+    if diagnostic.severity == Severity::Error && self.is_full() {
+      return;
+    }
+
+    // keep diagnostics in source order even though later passes find their problems last
+    let position = (diagnostic.span.file, diagnostic.span.line, diagnostic.span.column);
+    let index = self.diagnostics
+      .partition_point(|d| (d.span.file, d.span.line, d.span.column) <= position);
+    self.diagnostics.insert(index, diagnostic);
+  }
+
+  /// True once the error limit has been reached and assembly should stop.
+  pub fn is_full(&self) -> bool {
+    self.error_limit != 0 && self.error_count() >= self.error_limit
+  }
+
+  pub fn has_errors(&self) -> bool {
+    self.error_count() > 0
+  }
+
+  pub fn error_count(&self) -> usize {
+    self.count(Severity::Error)
+  }
+
+  pub fn warning_count(&self) -> usize {
+    self.count(Severity::Warning)
+  }
+
+  fn count(&self, severity: Severity) -> usize {
+    self.diagnostics.iter().filter(|d| d.severity == severity).count()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.diagnostics.is_empty()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &AssembleError> {
+    self.diagnostics.iter()
+  }
+
+  /// The same diagnostics with every warning counted as an error, for `--werror`.
+  pub fn into_errors(self) -> Diagnostics {
+    Diagnostics {
+      diagnostics: self.diagnostics
+        .into_iter()
+        .map(|d| AssembleError { severity: Severity::Error, ..d })
+        .collect(),
+      error_limit: self.error_limit,
+    }
+  }
+
+  pub fn with_source(self, file: &str, source: &str) -> Diagnostics {
+    Diagnostics {
+      diagnostics: self.diagnostics
+        .into_iter()
+        .map(|d| d.with_source(file, source))
+        .collect(),
+      error_limit: self.error_limit,
+    }
+  }
+
+  pub fn with_file(self, file: &str) -> Diagnostics {
+    Diagnostics {
+      diagnostics: self.diagnostics
+        .into_iter()
+        .map(|d| d.with_file(file))
+        .collect(),
+      error_limit: self.error_limit,
+    }
+  }
+
+  pub fn with_sources(self, sources: &Sources) -> Diagnostics {
+    Diagnostics {
+      diagnostics: self.diagnostics
+        .into_iter()
+        .map(|d| d.with_sources(sources))
+        .collect(),
+      error_limit: self.error_limit,
+    }
+  }
+}
+
+impl fmt::Display for Diagnostics {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for diagnostic in self.iter() {
+      writeln!(f, "{}\n", diagnostic)?;
+    }
+
+    let errors = self.error_count();
+    let warnings = self.warning_count();
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+
+    if errors > 0 {
+      if self.is_full() {
+        writeln!(f, "note: stopped after the first {} errors", errors)?;
+      }
+      write!(f, "aborting due to {} error{}", errors, plural(errors))?;
+      if warnings > 0 {
+        write!(f, " and {} warning{}", warnings, plural(warnings))?;
+      }
+    } else {
+      write!(f, "{} warning{} emitted", warnings, plural(warnings))?;
+    }
+
+    Ok(())
+  }
+}
+
+impl Error for Diagnostics {}
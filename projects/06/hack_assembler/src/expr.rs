@@ -0,0 +1,325 @@
+use std::fmt;
+
+use crate::error::ErrorKind;
+use crate::lexer::{self, TokenKind};
+use crate::literal::{is_literal_start, parse_literal};
+
+// constant expressions in A-instruction operands, e.g. `@SCREEN+32*10`.
+// offsets and lengths are in chars from the start of the operand.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+  Add,
+  Sub,
+  Mul,
+  Div,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+  Num(i64),
+  Symbol { name: String, offset: usize },
+  Neg(Box<Expr>),
+  Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Num(i64),
+  Symbol(String),
+  Op(char),
+}
+
+// a problem in an expression along with the chars it covers;
+// a length of 0 stands for the whole expression
+pub type ExprError = (ErrorKind, usize, usize);
+
+// `coefficient * symbol + constant`, with at most one relocatable symbol
+type Linear = (Option<(String, i64)>, i64);
+
+impl Expr {
+  pub fn parse(s: &str) -> Result<Expr, ExprError> {
+    let tokens = tokenize(s)?;
+    let mut parser = Parser { tokens: &tokens, position: 0, end: s.chars().count() };
+
+    let expr = parser.expr()?;
+    match parser.peek() {
+      None                    => Ok(expr),
+      Some((_, offset, len)) => Err((ErrorKind::BadExpression(String::from("expected an operator")), offset, len)),
+    }
+  }
+
+  // every symbol the expression refers to, with its offset
+  pub fn symbols(&self) -> Vec<(&str, usize)> {
+    match self {
+      Expr::Num(_)                  => Vec::new(),
+      Expr::Symbol { name, offset } => vec![(name.as_str(), *offset)],
+      Expr::Neg(expr)               => expr.symbols(),
+      Expr::Binary(_, lhs, rhs)     => {
+        let mut symbols = lhs.symbols();
+        symbols.extend(rhs.symbols());
+        symbols
+      },
+    }
+  }
+
+  // `lookup` gives the address of a symbol, or None if it has none yet
+  pub fn eval(&self, lookup: &dyn Fn(&str) -> Option<u16>) -> Result<i64, ExprError> {
+    let overflow = || (ErrorKind::BadExpression(String::from("arithmetic overflow")), 0, 0);
+
+    match self {
+      Expr::Num(value)              => Ok(*value),
+      Expr::Symbol { name, offset } => match lookup(name) {
+        Some(address) => Ok(i64::from(address)),
+        None          => Err((ErrorKind::UndefinedInExpression(name.clone()), *offset, name.chars().count())),
+      },
+      Expr::Neg(expr)               => expr.eval(lookup)?.checked_neg().ok_or_else(overflow),
+      Expr::Binary(op, lhs, rhs)    => {
+        let (lhs, rhs) = (lhs.eval(lookup)?, rhs.eval(lookup)?);
+
+        match op {
+          Op::Add => lhs.checked_add(rhs).ok_or_else(overflow),
+          Op::Sub => lhs.checked_sub(rhs).ok_or_else(overflow),
+          Op::Mul => lhs.checked_mul(rhs).ok_or_else(overflow),
+          Op::Div if rhs == 0 => Err((ErrorKind::BadExpression(String::from("division by zero")), 0, 0)),
+          Op::Div => Ok(lhs / rhs),
+        }
+      },
+    }
+  }
+
+  // the expression as `symbol + constant` for object files, where `relocatable`
+  // picks out the symbols only known once linked and `lookup` gives the others.
+  // anything else, such as two labels or a label times 2, cannot be relocated
+  pub fn relocation(
+    &self,
+    relocatable: &dyn Fn(&str) -> bool,
+    lookup: &dyn Fn(&str) -> Option<u16>,
+  ) -> Result<(Option<String>, i64), ExprError> {
+    let not_relocatable = || (ErrorKind::NotRelocatable(self.to_string()), 0, 0);
+
+    match self.linear(relocatable, lookup)? {
+      (Some((name, 1)), constant) => Ok((Some(name), constant)),
+      (Some(_), _)                => Err(not_relocatable()),
+      (None, constant)            => Ok((None, constant)),
+    }
+  }
+
+  fn linear(
+    &self,
+    relocatable: &dyn Fn(&str) -> bool,
+    lookup: &dyn Fn(&str) -> Option<u16>,
+  ) -> Result<Linear, ExprError> {
+    let overflow = || (ErrorKind::BadExpression(String::from("arithmetic overflow")), 0, 0);
+    let not_relocatable = || (ErrorKind::NotRelocatable(self.to_string()), 0, 0);
+    let scale = |(symbol, constant): Linear, factor: i64| {
+      let symbol = match symbol {
+        Some((name, coefficient)) => Some((name, coefficient.checked_mul(factor).ok_or_else(overflow)?)),
+        None                      => None,
+      };
+      Ok((symbol, constant.checked_mul(factor).ok_or_else(overflow)?))
+    };
+
+    match self {
+      Expr::Symbol { name, .. } if relocatable(name) => Ok((Some((name.clone(), 1)), 0)),
+      Expr::Num(_) | Expr::Symbol { .. } => Ok((None, self.eval(lookup)?)),
+      Expr::Neg(expr)            => scale(expr.linear(relocatable, lookup)?, -1),
+      Expr::Binary(op, lhs, rhs) => {
+        let (lhs, rhs) = (lhs.linear(relocatable, lookup)?, rhs.linear(relocatable, lookup)?);
+
+        match op {
+          Op::Add | Op::Sub => {
+            let rhs = if *op == Op::Sub { scale(rhs, -1)? } else { rhs };
+            let constant = lhs.1.checked_add(rhs.1).ok_or_else(overflow)?;
+
+            let symbol = match (lhs.0, rhs.0) {
+              (Some((a, x)), Some((b, y))) if a == b => Some((a, x + y)).filter(|(_, c)| *c != 0),
+              (Some(_), Some(_))                     => return Err(not_relocatable()),
+              (symbol, None) | (None, symbol)        => symbol,
+            };
+
+            Ok((symbol, constant))
+          },
+          Op::Mul => match (lhs, rhs) {
+            ((None, factor), other) | (other, (None, factor)) => scale(other, factor),
+            _ => Err(not_relocatable()),
+          },
+          Op::Div => match (lhs, rhs) {
+            ((None, _), (None, 0))       => Err((ErrorKind::BadExpression(String::from("division by zero")), 0, 0)),
+            ((None, lhs), (None, rhs))   => Ok((None, lhs / rhs)),
+            _                            => Err(not_relocatable()),
+          },
+        }
+      },
+    }
+  }
+}
+
+impl Op {
+  fn precedence(self) -> u8 {
+    match self {
+      Op::Add | Op::Sub => 1,
+      Op::Mul | Op::Div => 2,
+    }
+  }
+}
+
+impl fmt::Display for Op {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Op::Add => write!(f, "+"),
+      Op::Sub => write!(f, "-"),
+      Op::Mul => write!(f, "*"),
+      Op::Div => write!(f, "/"),
+    }
+  }
+}
+
+impl Expr {
+  fn precedence(&self) -> u8 {
+    match self {
+      Expr::Binary(op, _, _) => op.precedence(),
+      _                      => 3,
+    }
+  }
+}
+
+// written out with only the parentheses the precedence rules need
+impl fmt::Display for Expr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Expr::Num(value)           => write!(f, "{}", value),
+      Expr::Symbol { name, .. }  => write!(f, "{}", name),
+      Expr::Neg(expr) if expr.precedence() < 3 => write!(f, "-({})", expr),
+      Expr::Neg(expr)            => write!(f, "-{}", expr),
+      Expr::Binary(op, lhs, rhs) => {
+        if lhs.precedence() < op.precedence() {
+          write!(f, "({})", lhs)?;
+        } else {
+          write!(f, "{}", lhs)?;
+        }
+
+        write!(f, "{}", op)?;
+
+        // `-` and `/` do not associate, so an equal precedence on the right needs grouping
+        let grouped = match op {
+          Op::Sub | Op::Div => rhs.precedence() <= op.precedence(),
+          _                 => rhs.precedence() < op.precedence(),
+        };
+
+        if grouped {
+          write!(f, "({})", rhs)
+        } else {
+          write!(f, "{}", rhs)
+        }
+      },
+    }
+  }
+}
+
+// the expression's tokens with their char offsets and lengths, read from
+// the assembler's lexer
+fn tokenize(s: &str) -> Result<Vec<(Token, usize, usize)>, ExprError> {
+  let mut tokens = Vec::new();
+
+  for token in lexer::tokenize(s) {
+    let offset = s[..token.span.start].chars().count();
+    let len = token.text.chars().count();
+    let first = token.text.chars().next().unwrap_or(' ');
+
+    let expr_token = match token.kind {
+      TokenKind::Whitespace                                        => continue,
+      TokenKind::Punct if "+-*/()".contains(first)                 => Token::Op(first),
+      TokenKind::Word | TokenKind::Char if is_literal_start(first) => match parse_literal(token.text) {
+        Ok(value)   => Token::Num(value),
+        Err(reason) => return Err((ErrorKind::BadLiteral(String::from(token.text), reason), offset, len)),
+      },
+      TokenKind::Word                                              => Token::Symbol(String::from(token.text)),
+      _                                                            => {
+        let kind = ErrorKind::BadExpression(format!("unexpected character `{}`", first));
+        return Err((kind, offset, 1));
+      },
+    };
+
+    tokens.push((expr_token, offset, len));
+  }
+
+  Ok(tokens)
+}
+
+struct Parser<'a> {
+  tokens: &'a [(Token, usize, usize)],
+  position: usize,
+  end: usize,
+}
+
+impl<'a> Parser<'a> {
+  fn peek(&self) -> Option<(&'a Token, usize, usize)> {
+    self.tokens.get(self.position).map(|(token, offset, len)| (token, *offset, *len))
+  }
+
+  fn next_op(&mut self, ops: &str) -> Option<char> {
+    match self.peek() {
+      Some((Token::Op(c), _, _)) if ops.contains(*c) => {
+        self.position += 1;
+        Some(*c)
+      },
+      _ => None,
+    }
+  }
+
+  // expr := term (('+' | '-') term)*
+  fn expr(&mut self) -> Result<Expr, ExprError> {
+    let mut expr = self.term()?;
+
+    while let Some(c) = self.next_op("+-") {
+      let op = if c == '+' { Op::Add } else { Op::Sub };
+      expr = Expr::Binary(op, Box::new(expr), Box::new(self.term()?));
+    }
+
+    Ok(expr)
+  }
+
+  // term := unary (('*' | '/') unary)*
+  fn term(&mut self) -> Result<Expr, ExprError> {
+    let mut expr = self.unary()?;
+
+    while let Some(c) = self.next_op("*/") {
+      let op = if c == '*' { Op::Mul } else { Op::Div };
+      expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+    }
+
+    Ok(expr)
+  }
+
+  // unary := '-' unary | number | symbol | '(' expr ')'
+  fn unary(&mut self) -> Result<Expr, ExprError> {
+    if self.next_op("-").is_some() {
+      return Ok(Expr::Neg(Box::new(self.unary()?)));
+    }
+
+    match self.peek() {
+      Some((Token::Num(value), _, _))     => {
+        self.position += 1;
+        Ok(Expr::Num(*value))
+      },
+      Some((Token::Symbol(name), offset, _)) => {
+        self.position += 1;
+        Ok(Expr::Symbol { name: name.clone(), offset })
+      },
+      Some((Token::Op('('), offset, _))   => {
+        self.position += 1;
+        let expr = self.expr()?;
+
+        match self.next_op(")") {
+          Some(_) => Ok(expr),
+          None    => Err((ErrorKind::BadExpression(String::from("unclosed `(`")), offset, 1)),
+        }
+      },
+      Some((Token::Op(c), offset, len))   =>
+        Err((ErrorKind::BadExpression(format!("expected a value, found `{}`", c)), offset, len)),
+      None                                =>
+        Err((ErrorKind::BadExpression(String::from("expected a value")), self.end, 1)),
+    }
+  }
+}
@@ -0,0 +1,340 @@
+use crate::error::{AssembleError, Diagnostics, ErrorKind, Span};
+
+/// A file format for machine code, for the course tools, FPGA toolchains
+/// and Logisim.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+  /// The course's text format: one word per line as 16 `0`/`1` characters.
+  Hack,
+  /// Two bytes per word, most significant byte first.
+  BinaryBigEndian,
+  /// Two bytes per word, least significant byte first.
+  BinaryLittleEndian,
+  /// Intel HEX for a 16-bit wide ROM: record addresses count words, one
+  /// word per record, most significant byte first.
+  IntelHex,
+  /// Verilog `$readmemb`: one word per line in binary.
+  ReadmemB,
+  /// Verilog `$readmemh`: one word per line in hex.
+  ReadmemH,
+  /// Logisim's `v2.0 raw` ROM image.
+  Logisim,
+}
+
+// each format's name for --format and its file extension; where two share an
+// extension the first is what the extension means
+const FORMATS: [(&str, &str, Format); 7] = [
+  ("hack"    , "hack", Format::Hack),
+  ("bin"     , "bin" , Format::BinaryBigEndian),
+  ("bin-le"  , "bin" , Format::BinaryLittleEndian),
+  ("ihex"    , "hex" , Format::IntelHex),
+  ("readmemb", "memb", Format::ReadmemB),
+  ("readmemh", "memh", Format::ReadmemH),
+  ("logisim" , "rom" , Format::Logisim),
+];
+
+const LOGISIM_HEADER: &str = "v2.0 raw";
+
+// as many words as a 16-bit address can reach; nothing bigger is a Hack program
+const MAX_WORDS: usize = 65536;
+
+impl Format {
+  pub fn from_name(name: &str) -> Option<Format> {
+    FORMATS.iter().find(|(known, _, _)| *known == name).map(|(_, _, format)| *format)
+  }
+
+  pub fn from_extension(extension: &str) -> Option<Format> {
+    FORMATS.iter().find(|(_, known, _)| *known == extension).map(|(_, _, format)| *format)
+  }
+
+  pub fn extension(self) -> &'static str {
+    FORMATS.iter().find(|(_, _, format)| *format == self).map_or("", |(_, extension, _)| extension)
+  }
+
+  // whether the format is text, so diagnostics can quote its lines
+  pub fn is_text(self) -> bool {
+    !matches!(self, Format::BinaryBigEndian | Format::BinaryLittleEndian)
+  }
+}
+
+/// Machine code words as the bytes of a file in `format`.
+pub fn write_machine_code(words: &[u16], format: Format) -> Vec<u8> {
+  match format {
+    Format::Hack               => to_hack(words).into_bytes(),
+    Format::BinaryBigEndian    => words.iter().flat_map(|word| word.to_be_bytes()).collect(),
+    Format::BinaryLittleEndian => words.iter().flat_map(|word| word.to_le_bytes()).collect(),
+    Format::IntelHex           => to_intel_hex(words).into_bytes(),
+    Format::ReadmemB           => words.iter().map(|word| format!("{:016b}\n", word)).collect::<String>().into_bytes(),
+    Format::ReadmemH           => words.iter().map(|word| format!("{:04x}\n", word)).collect::<String>().into_bytes(),
+    Format::Logisim            => to_logisim(words).into_bytes(),
+  }
+}
+
+/// The words of a machine code file, with where each came from: the word's
+/// text in a text format, or its number in a binary one.
+pub fn read_machine_code(input: &[u8], format: Format, diagnostics: &mut Diagnostics) -> (Vec<u16>, Vec<Span>) {
+  let text = String::from_utf8_lossy(input);
+
+  match format {
+    Format::Hack               => from_hack(&text, diagnostics),
+    Format::BinaryBigEndian    => from_binary(input, u16::from_be_bytes, diagnostics),
+    Format::BinaryLittleEndian => from_binary(input, u16::from_le_bytes, diagnostics),
+    Format::IntelHex           => from_intel_hex(&text, diagnostics),
+    Format::ReadmemB           => from_readmem(&text, 2, diagnostics),
+    Format::ReadmemH           => from_readmem(&text, 16, diagnostics),
+    Format::Logisim            => from_logisim(&text, diagnostics),
+  }
+}
+
+// the course's text format: one word per line as 16 ASCII '0'/'1' characters
+pub fn to_hack(words: &[u16]) -> String {
+  let mut output = String::with_capacity(words.len() * 17);
+
+  for word in words {
+    for bit in (0..16).rev() {
+      output.push(if word >> bit & 1 == 1 { '1' } else { '0' });
+    }
+    output.push('\n');
+  }
+
+  output
+}
+
+pub fn from_hack(input: &str, diagnostics: &mut Diagnostics) -> (Vec<u16>, Vec<Span>) {
+  let mut words = Vec::new();
+  let mut locations = Vec::new();
+
+  for (index, line) in input.split('\n').enumerate() {
+    let word = line.trim();
+    if word.is_empty() {
+      continue;
+    }
+
+    let indent = line.len() - line.trim_start().len();
+    let span = Span::new(index + 1, line[..indent].chars().count() + 1, word.chars().count());
+    if word.len() != 16 || !word.chars().all(|c| c == '0' || c == '1') {
+      diagnostics.push(AssembleError::new(ErrorKind::MalformedWord(String::from(word)), span));
+      continue;
+    }
+
+    words.push(u16::from_str_radix(word, 2).unwrap());
+    locations.push(span);
+  }
+
+  (words, locations)
+}
+
+// binary files have no lines, so a word's span gives its number instead
+fn from_binary(input: &[u8], word: fn([u8; 2]) -> u16, diagnostics: &mut Diagnostics) -> (Vec<u16>, Vec<Span>) {
+  if !input.len().is_multiple_of(2) {
+    let why = format!("{} bytes is not a whole number of 16-bit words", input.len());
+    diagnostics.push(AssembleError::new(ErrorKind::MalformedMachineCode(why), Span::new(1, 1, 0)));
+  }
+
+  let words: Vec<u16> = input.chunks_exact(2).map(|bytes| word([bytes[0], bytes[1]])).collect();
+  let locations = (0..words.len()).map(|address| Span::new(address + 1, 1, 0)).collect();
+  (words, locations)
+}
+
+fn to_intel_hex(words: &[u16]) -> String {
+  let mut output = String::new();
+
+  for (address, word) in words.iter().enumerate() {
+    let bytes = [2, (address >> 8) as u8, address as u8, 0x00, (word >> 8) as u8, *word as u8];
+    output.push_str(&intel_hex_record(&bytes));
+  }
+
+  output.push_str(&intel_hex_record(&[0, 0, 0, 0x01]));
+  output
+}
+
+// `:`, the bytes in hex and the checksum that makes them all add up to 0
+fn intel_hex_record(bytes: &[u8]) -> String {
+  let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+  let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+
+  format!(":{}{:02X}\n", hex, sum.wrapping_neg())
+}
+
+fn from_intel_hex(input: &str, diagnostics: &mut Diagnostics) -> (Vec<u16>, Vec<Span>) {
+  let mut words = Vec::new();
+  let mut locations = Vec::new();
+
+  for (index, line) in input.split('\n').enumerate() {
+    let record = line.trim();
+    if record.is_empty() {
+      continue;
+    }
+
+    let span = Span::new(index + 1, line.len() - line.trim_start().len() + 1, record.len());
+    let malformed = |why: &str| AssembleError::new(ErrorKind::MalformedMachineCode(String::from(why)), span);
+
+    let hex = match record.strip_prefix(':') {
+      Some(hex) if hex.len() >= 10 && hex.len().is_multiple_of(2) && hex.chars().all(|c| c.is_ascii_hexdigit()) => hex,
+      _ => {
+        diagnostics.push(malformed("an Intel HEX record is `:` followed by pairs of hex digits"));
+        continue;
+      },
+    };
+
+    let bytes: Vec<u8> = (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect();
+    let data = &bytes[4..bytes.len() - 1];
+
+    if usize::from(bytes[0]) != data.len() {
+      diagnostics.push(malformed("the byte count does not match the record's length"));
+      continue;
+    }
+
+    if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+      diagnostics.push(malformed("bad checksum"));
+      continue;
+    }
+
+    let address = usize::from(bytes[1]) << 8 | usize::from(bytes[2]);
+    match bytes[3] {
+      0x00 if data.len().is_multiple_of(2) => {
+        for (i, pair) in data.chunks_exact(2).enumerate() {
+          if !place(&mut words, &mut locations, address + i, u16::from_be_bytes([pair[0], pair[1]]), span) {
+            diagnostics.push(malformed("the record goes past address FFFF"));
+            break;
+          }
+        }
+      },
+      0x00 => diagnostics.push(malformed("data records must hold whole 16-bit words")),
+      0x01 => break,
+      kind => diagnostics.push(malformed(&format!("record type {:02X} does not fit a Hack ROM", kind))),
+    }
+  }
+
+  (words, locations)
+}
+
+// Verilog memory files: words in the given radix separated by whitespace,
+// `//` comments, `_` between digits and `@address` to move on to a word
+fn from_readmem(input: &str, radix: u32, diagnostics: &mut Diagnostics) -> (Vec<u16>, Vec<Span>) {
+  let mut words = Vec::new();
+  let mut locations = Vec::new();
+  let mut address = 0;
+
+  for (index, line) in input.split('\n').enumerate() {
+    let code = line.find("//").map_or(line, |comment_index| &line[..comment_index]);
+
+    for (column, token) in tokens(code) {
+      let span = Span::new(index + 1, column, token.chars().count());
+      let digits = token.replace('_', "");
+
+      if let Some(target) = digits.strip_prefix('@') {
+        match usize::from_str_radix(target, 16) {
+          Ok(target) => address = target,
+          Err(_)     => {
+            let why = format!("`{}` is not an address in hex", token);
+            diagnostics.push(AssembleError::new(ErrorKind::MalformedMachineCode(why), span));
+          },
+        }
+        continue;
+      }
+
+      let why = match u16::from_str_radix(&digits, radix) {
+        Ok(word) if !digits.starts_with('+') => {
+          if place(&mut words, &mut locations, address, word, span) {
+            address += 1;
+            continue;
+          }
+          format!("`{}` would go past address FFFF", token)
+        },
+        _ => format!("`{}` is not a 16-bit word in {}", token, if radix == 2 { "binary" } else { "hex" }),
+      };
+
+      diagnostics.push(AssembleError::new(ErrorKind::MalformedMachineCode(why), span));
+      address += 1;
+    }
+  }
+
+  (words, locations)
+}
+
+// Logisim writes eight words per line and runs of a word as `count*word`
+fn to_logisim(words: &[u16]) -> String {
+  let mut output = format!("{}\n", LOGISIM_HEADER);
+
+  for line in words.chunks(8) {
+    let line: Vec<String> = line.iter().map(|word| format!("{:x}", word)).collect();
+    output.push_str(&line.join(" "));
+    output.push('\n');
+  }
+
+  output
+}
+
+fn from_logisim(input: &str, diagnostics: &mut Diagnostics) -> (Vec<u16>, Vec<Span>) {
+  let mut words = Vec::new();
+  let mut locations = Vec::new();
+  let mut lines = input.split('\n').enumerate();
+
+  if lines.next().map(|(_, header)| header.trim()) != Some(LOGISIM_HEADER) {
+    let why = format!("expected a `{}` header", LOGISIM_HEADER);
+    diagnostics.push(AssembleError::new(ErrorKind::MalformedMachineCode(why), Span::new(1, 1, 0)));
+    return (words, locations);
+  }
+
+  for (index, line) in lines {
+    let code = line.find('#').map_or(line, |comment_index| &line[..comment_index]);
+
+    for (column, token) in tokens(code) {
+      let (count, digits) = match token.split_once('*') {
+        Some((count, digits)) => (count.parse::<usize>().ok(), digits),
+        None                  => (Some(1), token),
+      };
+
+      let span = Span::new(index + 1, column, token.chars().count());
+      match (count, u16::from_str_radix(digits, 16)) {
+        (Some(count), _) if words.len() + count > MAX_WORDS => {
+          let why = format!("`{}` would go past address FFFF", token);
+          diagnostics.push(AssembleError::new(ErrorKind::MalformedMachineCode(why), span));
+        },
+        (Some(count), Ok(word)) if !digits.starts_with('+') => {
+          words.resize(words.len() + count, word);
+          locations.resize(words.len(), span);
+        },
+        _ => {
+          let why = format!("`{}` is not a hex word or `count*word`", token);
+          diagnostics.push(AssembleError::new(ErrorKind::MalformedMachineCode(why), span));
+        },
+      }
+    }
+  }
+
+  (words, locations)
+}
+
+// the whitespace-separated tokens of a line with their 1-based columns
+fn tokens(line: &str) -> Vec<(usize, &str)> {
+  let mut tokens = Vec::new();
+  let mut rest = line;
+
+  while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+    let token = rest[start..].split_whitespace().next().unwrap();
+    let offset = line.len() - rest.len() + start;
+    tokens.push((line[..offset].chars().count() + 1, token));
+    rest = &rest[start + token.len()..];
+  }
+
+  tokens
+}
+
+// put `word` read from `span` at `address`, filling any gap before it with
+// zeros from the same place; false if the address is out of reach
+fn place(words: &mut Vec<u16>, locations: &mut Vec<Span>, address: usize, word: u16, span: Span) -> bool {
+  if address >= MAX_WORDS {
+    return false;
+  }
+
+  if words.len() <= address {
+    words.resize(address + 1, 0);
+    locations.resize(address + 1, span);
+  }
+
+  words[address] = word;
+  locations[address] = span;
+  true
+}
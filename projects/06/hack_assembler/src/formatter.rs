@@ -0,0 +1,112 @@
+use crate::code::encode_c;
+use crate::disassembler::c_instruction;
+use crate::lexer::{tokenize, TokenKind};
+use crate::syntax::{LineKind, SyntaxLine, SyntaxTree};
+use crate::Instruction;
+
+const INDENT: &str = "    ";
+
+// the least space between code and a trailing comment
+const COMMENT_GAP: usize = 2;
+
+/// Rewrite assembly source in canonical form.
+///
+/// Labels and preprocessor block directives such as `.macro` are flush
+/// left and everything else is indented. C-instructions are spelt the way
+/// the disassembler writes them, so `M = M + D` becomes `M=D+M`. Trailing
+/// comments line up within each group of lines, runs of blank lines between
+/// groups become one, and comment lines are indented like the code they sit
+/// against. Lines that do not parse are only re-indented.
+pub fn format_source(source: &str) -> String {
+  let tree = SyntaxTree::parse(source);
+  let newline = if tree.is_crlf() { "\r\n" } else { "\n" };
+
+  let groups: Vec<&[SyntaxLine]> = tree.lines
+    .split(|line| line.kind == LineKind::Blank)
+    .filter(|group| !group.is_empty())
+    .collect();
+
+  // a byte order mark stays, since some Windows tools want it
+  let bom = tree.bom.as_ref().map_or("", |bom| bom.text);
+  let formatted: Vec<String> = groups.iter().map(|group| format_group(group, newline)).collect();
+  format!("{}{}", bom, formatted.join(newline))
+}
+
+fn format_group(group: &[SyntaxLine], newline: &str) -> String {
+  let lines: Vec<(&str, String, Option<&str>)> = group
+    .iter()
+    .enumerate()
+    .map(|(i, line)| (indent(group, i), canonical(line), line.comment()))
+    .collect();
+
+  // trailing comments start in one column, past the longest code that has one
+  let column = lines
+    .iter()
+    .filter(|(_, code, comment)| !code.is_empty() && comment.is_some())
+    .map(|(indent, code, _)| indent.len() + code.chars().count() + COMMENT_GAP)
+    .max()
+    .unwrap_or(0);
+
+  let mut output = String::new();
+  for (indent, code, comment) in lines {
+    let text = match comment {
+      Some(comment) if !code.is_empty() => {
+        let padding = column - indent.len() - code.chars().count();
+        format!("{}{}{}{}", indent, code, " ".repeat(padding), comment)
+      },
+      Some(comment) => format!("{}{}", indent, comment),
+      None          => format!("{}{}", indent, code),
+    };
+
+    output.push_str(&text);
+    output.push_str(newline);
+  }
+
+  output
+}
+
+// comment lines take the indent of the code below them, or above them at
+// the end of a group
+fn indent(group: &[SyntaxLine], i: usize) -> &'static str {
+  let code_indent = |line: &SyntaxLine| match line.kind {
+    LineKind::Code                                      => Some(INDENT),
+    LineKind::Label | LineKind::Directive               => Some(""),
+    LineKind::Comment | LineKind::Blank                 => None,
+  };
+
+  code_indent(&group[i])
+    .or_else(|| group[i..].iter().find_map(code_indent))
+    .or_else(|| group[..i].iter().rev().find_map(code_indent))
+    .unwrap_or("")
+}
+
+fn canonical(line: &SyntaxLine) -> String {
+  let code = line.code();
+
+  match Instruction::get(code, line.span()) {
+    Ok(Instruction::LInstruction(label))               => format!("({})", label),
+    Ok(Instruction::NumericLabel(label))               => format!("{}:", label),
+    _ if line.kind != LineKind::Code                   => spaced(code),
+    Ok(Instruction::CInstruction { dest, comp, jump }) => c_instruction(encode_c(dest, comp, jump)),
+    Ok(Instruction::AInstruction(_))                   => format!("@{}", code[1..].trim()),
+    Ok(Instruction::Pseudo(_))                         => {
+      let mnemonic = code.split_whitespace().next().unwrap_or("");
+      let operands = code[mnemonic.len()..].trim();
+
+      match operands.split_once(',') {
+        Some((register, value)) => format!("{} {}, {}", mnemonic, register.trim(), value.trim()),
+        None                    => spaced(code),
+      }
+    },
+    // macro calls and lines with errors are left for the assembler to report
+    Ok(_) | Err(_)                                     => spaced(code),
+  }
+}
+
+// the tokens of a line one space apart where there is any space between them
+fn spaced(code: &str) -> String {
+  tokenize(code)
+    .iter()
+    .map(|token| if token.kind == TokenKind::Whitespace { " " } else { token.text })
+    .collect()
+}
@@ -0,0 +1,322 @@
+use std::fmt;
+
+// just enough JSON for the language server's messages: objects keep their
+// keys in order, and numbers are f64 like JavaScript's
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Array(Vec<Json>),
+  Object(Vec<(String, Json)>),
+}
+
+impl Json {
+  pub fn parse(input: &str) -> Result<Json, String> {
+    let mut parser = Parser { chars: input.char_indices().peekable(), input };
+    let value = parser.value()?;
+
+    parser.skip_whitespace();
+    match parser.chars.peek() {
+      Some((index, _)) => Err(format!("unexpected text at byte {}", index)),
+      None             => Ok(value),
+    }
+  }
+
+  // an object from `(key, value)` pairs, for building messages
+  pub fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(fields.into_iter().map(|(key, value)| (String::from(key), value)).collect())
+  }
+
+  pub fn get(&self, key: &str) -> &Json {
+    match self {
+      Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map_or(&Json::Null, |(_, value)| value),
+      _                    => &Json::Null,
+    }
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Json::String(s) => Some(s),
+      _               => None,
+    }
+  }
+
+  pub fn as_u64(&self) -> Option<u64> {
+    match self {
+      Json::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as u64),
+      _                                                => None,
+    }
+  }
+
+  pub fn as_bool(&self) -> Option<bool> {
+    match self {
+      Json::Bool(b) => Some(*b),
+      _             => None,
+    }
+  }
+
+  pub fn as_array(&self) -> Option<&[Json]> {
+    match self {
+      Json::Array(items) => Some(items),
+      _                  => None,
+    }
+  }
+
+  pub fn is_null(&self) -> bool {
+    *self == Json::Null
+  }
+}
+
+impl From<&str> for Json {
+  fn from(s: &str) -> Json {
+    Json::String(String::from(s))
+  }
+}
+
+impl From<String> for Json {
+  fn from(s: String) -> Json {
+    Json::String(s)
+  }
+}
+
+impl From<bool> for Json {
+  fn from(b: bool) -> Json {
+    Json::Bool(b)
+  }
+}
+
+impl From<usize> for Json {
+  fn from(n: usize) -> Json {
+    Json::Number(n as f64)
+  }
+}
+
+impl From<Vec<Json>> for Json {
+  fn from(items: Vec<Json>) -> Json {
+    Json::Array(items)
+  }
+}
+
+impl fmt::Display for Json {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Json::Null                                            => write!(f, "null"),
+      Json::Bool(b)                                         => write!(f, "{}", b),
+      // whole numbers without the `.0` Rust would add
+      Json::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+      Json::Number(n)                                       => write!(f, "{}", n),
+      Json::String(s)                                       => write_string(f, s),
+      Json::Array(items)                                    => {
+        write!(f, "[")?;
+        for (i, item) in items.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write!(f, "{}", item)?;
+        }
+        write!(f, "]")
+      },
+      Json::Object(fields)                                  => {
+        write!(f, "{{")?;
+        for (i, (key, value)) in fields.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write_string(f, key)?;
+          write!(f, ":{}", value)?;
+        }
+        write!(f, "}}")
+      },
+    }
+  }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+  write!(f, "\"")?;
+
+  for c in s.chars() {
+    match c {
+      '"'                    => write!(f, "\\\"")?,
+      '\\'                   => write!(f, "\\\\")?,
+      '\n'                   => write!(f, "\\n")?,
+      '\r'                   => write!(f, "\\r")?,
+      '\t'                   => write!(f, "\\t")?,
+      c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+      c                      => write!(f, "{}", c)?,
+    }
+  }
+
+  write!(f, "\"")
+}
+
+struct Parser<'a> {
+  chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+  input: &'a str,
+}
+
+impl<'a> Parser<'a> {
+  fn value(&mut self) -> Result<Json, String> {
+    self.skip_whitespace();
+
+    match self.chars.peek().copied() {
+      Some((_, '{'))                                 => self.object(),
+      Some((_, '['))                                 => self.array(),
+      Some((_, '"'))                                 => self.string().map(Json::String),
+      Some((_, c)) if c == '-' || c.is_ascii_digit() => self.number(),
+      Some((index, _))                               => {
+        let rest = &self.input[index..];
+        let literal = [("null", Json::Null), ("true", Json::Bool(true)), ("false", Json::Bool(false))]
+          .iter()
+          .find(|(word, _)| rest.starts_with(word))
+          .cloned();
+
+        match literal {
+          Some((word, value)) => {
+            word.chars().for_each(|_| { self.chars.next(); });
+            Ok(value)
+          },
+          None                => Err(format!("unexpected text at byte {}", index)),
+        }
+      },
+      None                                           => Err(String::from("unexpected end of input")),
+    }
+  }
+
+  fn object(&mut self) -> Result<Json, String> {
+    self.chars.next();
+    let mut fields = Vec::new();
+
+    self.skip_whitespace();
+    if self.eat('}') {
+      return Ok(Json::Object(fields));
+    }
+
+    loop {
+      self.skip_whitespace();
+      let key = self.string()?;
+
+      self.skip_whitespace();
+      if !self.eat(':') {
+        return Err(String::from("expected `:` after an object key"));
+      }
+
+      fields.push((key, self.value()?));
+
+      self.skip_whitespace();
+      if self.eat('}') {
+        return Ok(Json::Object(fields));
+      }
+      if !self.eat(',') {
+        return Err(String::from("expected `,` or `}` in an object"));
+      }
+    }
+  }
+
+  fn array(&mut self) -> Result<Json, String> {
+    self.chars.next();
+    let mut items = Vec::new();
+
+    self.skip_whitespace();
+    if self.eat(']') {
+      return Ok(Json::Array(items));
+    }
+
+    loop {
+      items.push(self.value()?);
+
+      self.skip_whitespace();
+      if self.eat(']') {
+        return Ok(Json::Array(items));
+      }
+      if !self.eat(',') {
+        return Err(String::from("expected `,` or `]` in an array"));
+      }
+    }
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    if !self.eat('"') {
+      return Err(String::from("expected a string"));
+    }
+
+    let mut s = String::new();
+    loop {
+      let c = match self.chars.next() {
+        Some((_, '"'))  => return Ok(s),
+        Some((_, '\\')) => match self.chars.next() {
+          Some((_, 'n')) => '\n',
+          Some((_, 'r')) => '\r',
+          Some((_, 't')) => '\t',
+          Some((_, 'b')) => '\u{8}',
+          Some((_, 'f')) => '\u{c}',
+          Some((_, 'u')) => self.unicode_escape()?,
+          Some((_, c))   => c,
+          None           => return Err(String::from("unterminated string")),
+        },
+        Some((_, c))    => c,
+        None            => return Err(String::from("unterminated string")),
+      };
+
+      s.push(c);
+    }
+  }
+
+  // `\uXXXX`, with a second escape for the low half of a surrogate pair
+  fn unicode_escape(&mut self) -> Result<char, String> {
+    let high = self.hex4()?;
+    if !(0xD800..0xDC00).contains(&high) {
+      return Ok(char::from_u32(high).unwrap_or('\u{fffd}'));
+    }
+
+    if !(self.eat('\\') && self.eat('u')) {
+      return Ok('\u{fffd}');
+    }
+
+    let low = self.hex4()?;
+    let c = 0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+    Ok(char::from_u32(c).unwrap_or('\u{fffd}'))
+  }
+
+  fn hex4(&mut self) -> Result<u32, String> {
+    let digits: String = (0..4).filter_map(|_| self.chars.next().map(|(_, c)| c)).collect();
+    u32::from_str_radix(&digits, 16).map_err(|_| format!("bad escape `\\u{}`", digits))
+  }
+
+  fn number(&mut self) -> Result<Json, String> {
+    let start = self.chars.peek().map_or(self.input.len(), |(index, _)| *index);
+    while let Some((_, c)) = self.chars.peek() {
+      if c.is_ascii_digit() || "+-.eE".contains(*c) {
+        self.chars.next();
+      } else {
+        break;
+      }
+    }
+
+    let end = self.chars.peek().map_or(self.input.len(), |(index, _)| *index);
+    self.input[start..end]
+      .parse()
+      .map(Json::Number)
+      .map_err(|_| format!("bad number `{}`", &self.input[start..end]))
+  }
+
+  fn eat(&mut self, expected: char) -> bool {
+    match self.chars.peek() {
+      Some((_, c)) if *c == expected => {
+        self.chars.next();
+        true
+      },
+      _ => false,
+    }
+  }
+
+  fn skip_whitespace(&mut self) {
+    while let Some((_, c)) = self.chars.peek() {
+      if !c.is_whitespace() {
+        break;
+      }
+      self.chars.next();
+    }
+  }
+}
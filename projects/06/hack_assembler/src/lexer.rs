@@ -0,0 +1,133 @@
+use std::ops::Range;
+
+const BOM: char = '\u{feff}';
+
+/// What a token is. Everything but `Word`, `String`, `Char` and `Punct` is
+/// trivia, which the assembler skips and the formatter keeps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+  /// A byte order mark at the very start of the source.
+  Bom,
+  /// Spaces and tabs.
+  Whitespace,
+  /// `\n`, `\r\n` or a lone `\r`.
+  Newline,
+  /// `//` to the end of the line, without trailing whitespace.
+  Comment,
+  /// A run of symbol chars: a name, a number, a mnemonic, a directive such
+  /// as `.data` or a numeric label such as `1:`.
+  Word,
+  /// `"text"`, with `\` escapes; unterminated at the end of a line.
+  String,
+  /// `'c'`, with `\` escapes; unterminated at the end of a line.
+  Char,
+  /// Any other single char, such as `@`, `=`, `;` or `(`.
+  Punct,
+}
+
+/// A token with its text and its byte range in the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token<'a> {
+  pub kind: TokenKind,
+  pub text: &'a str,
+  pub span: Range<usize>,
+}
+
+impl<'a> Token<'a> {
+  pub fn is_trivia(&self) -> bool {
+    matches!(self.kind, TokenKind::Bom | TokenKind::Whitespace | TokenKind::Newline | TokenKind::Comment)
+  }
+
+  pub fn is_punct(&self, punct: &str) -> bool {
+    self.kind == TokenKind::Punct && self.text == punct
+  }
+}
+
+// symbols are letters, digits, `_`, `.`, `$` and `:`
+pub fn is_symbol_char(c: char) -> bool {
+  c.is_ascii_alphanumeric() || "_.$:".contains(c)
+}
+
+/// Split source into tokens. Every byte belongs to exactly one token, so
+/// their texts put together give the source back.
+pub fn tokenize(source: &str) -> Vec<Token<'_>> {
+  let mut tokens = Vec::new();
+  let mut rest = source;
+
+  if let Some(after) = rest.strip_prefix(BOM) {
+    tokens.push(Token { kind: TokenKind::Bom, text: &source[..BOM.len_utf8()], span: 0..BOM.len_utf8() });
+    rest = after;
+  }
+
+  while let Some(c) = rest.chars().next() {
+    let (kind, len) = match c {
+      '\r' if rest.starts_with("\r\n")        => (TokenKind::Newline, 2),
+      '\n' | '\r'                             => (TokenKind::Newline, 1),
+      '/' if rest.starts_with("//")           => {
+        let line = &rest[..line_len(rest)];
+        (TokenKind::Comment, line.trim_end().len())
+      },
+      '"'                                     => (TokenKind::String, quoted_len(rest)),
+      '\''                                    => (TokenKind::Char, quoted_len(rest)),
+      c if is_symbol_char(c)                  => (TokenKind::Word, run_len(rest, is_symbol_char)),
+      c if c.is_whitespace()                  => (TokenKind::Whitespace, run_len(rest, |c| c.is_whitespace() && c != '\n' && c != '\r')),
+      c                                       => (TokenKind::Punct, c.len_utf8()),
+    };
+
+    let start = source.len() - rest.len();
+    tokens.push(Token { kind, text: &rest[..len], span: start..start + len });
+    rest = &rest[len..];
+  }
+
+  tokens
+}
+
+/// The lines of the source, split the way [`tokenize`] does and without the
+/// byte order mark, so line `n` here is line `n` of every span.
+pub fn lines(source: &str) -> Vec<&str> {
+  let mut lines = Vec::new();
+  let mut rest = source.strip_prefix(BOM).unwrap_or(source);
+
+  loop {
+    let len = line_len(rest);
+    lines.push(&rest[..len]);
+
+    rest = &rest[len..];
+    if let Some(after) = rest.strip_prefix("\r\n").or_else(|| rest.strip_prefix('\n')).or_else(|| rest.strip_prefix('\r')) {
+      rest = after;
+    } else {
+      return lines;
+    }
+  }
+}
+
+// bytes up to the end of the line
+fn line_len(s: &str) -> usize {
+  s.find(['\n', '\r']).unwrap_or(s.len())
+}
+
+fn run_len(s: &str, f: impl Fn(char) -> bool) -> usize {
+  s.find(|c: char| !f(c)).unwrap_or(s.len())
+}
+
+// a quoted string or char up to and including its closing quote, or to the
+// end of the line if it has none
+fn quoted_len(s: &str) -> usize {
+  let quote = s.chars().next().unwrap();
+  let mut chars = s.char_indices().skip(1);
+
+  while let Some((index, c)) = chars.next() {
+    match c {
+      '\\'                    => {
+        if let Some((_, '\n')) | Some((_, '\r')) = chars.next() {
+          return index + 1;
+        }
+      },
+      '\n' | '\r'             => return index,
+      c if c == quote         => return index + c.len_utf8(),
+      _                       => (),
+    }
+  }
+
+  s.len()
+}
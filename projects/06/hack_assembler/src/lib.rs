@@ -1,324 +1,1677 @@
+//! Assembler for the Hack machine language.
+//!
+//! [`assemble`] turns source held in memory into a [`Program`]; [`run`] is the
+//! command-line driver that reads and writes files.
+
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::io::{self, Read, Write};
 use std::error::Error;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
-use std::collections::HashMap;
+use std::ops::Range;
 
-pub struct Config {
-  input_filename: String,
+mod code;
+mod disassembler;
+mod error;
+mod expr;
+mod format;
+mod formatter;
+mod json;
+mod lexer;
+mod linker;
+mod listing;
+mod literal;
+mod lsp;
+mod object;
+mod optimizer;
+mod preprocessor;
+mod source;
+mod symbol_table;
+mod syntax;
+mod typo;
+
+use expr::Expr;
+use lexer::is_symbol_char;
+use literal::{parse_literal, parse_string};
+use optimizer::optimize;
+use preprocessor::preprocess;
+use syntax::is_numeric_label;
+use typo::check_variables;
+
+pub use code::{encode_c, Comp, Dest, Jump};
+pub use disassembler::{disassemble, Symbols};
+pub use error::{AssembleError, Diagnostics, ErrorKind, Severity, Span, Suspicion};
+pub use format::{from_hack, read_machine_code, to_hack, write_machine_code, Format};
+pub use formatter::format_source;
+pub use lexer::{lines, tokenize, Token, TokenKind};
+pub use linker::link;
+pub use listing::listing;
+pub use lsp::run_language_server;
+pub use object::{Object, ObjectWord};
+pub use source::{SourceFile, Sources};
+pub use symbol_table::{Symbol, SymbolKind, SymbolTable};
+pub use syntax::{LineKind, SyntaxLine, SyntaxTree};
+
+const DEFAULT_ERROR_LIMIT: usize = 50;
+
+// A-instructions carry 15 bits; the 16th would turn the word into a C-instruction
+const MAX_LITERAL: i64 = 32767;
+
+const ROM_SIZE: usize = 32768;
+
+// where variables go unless told otherwise: after R0..R15, up to the screen
+const DEFAULT_VARIABLE_BASE: u16 = 16;
+const DEFAULT_VARIABLE_LIMIT: u16 = 16383;
+
+// the RAM the VM translator uses for its stack
+const VM_STACK_START: u32 = 256;
+const VM_STACK_END: u32 = 2047;
+
+const USAGE: &str = "\
+usage: hack_assembler [options] <file>...
+
+Assembles .asm files, disassembles machine code and links .hobj object files.
+Each input gets its own output next to it; `-` reads assembly from stdin.
+
+options:
+  -o, --output <file>       write the output here, `-` for stdout
+      --format <format>     hack, bin, bin-le, ihex, readmemb, readmemh or logisim
+      --fmt                 rewrite .asm files in canonical form
+      --check               with --fmt, fail if a file is not formatted
+  -c, --object              assemble to a relocatable .hobj object file
+      --listing             also write a .lst listing
+      --symbols[=sym|json]  also write the symbol table
+      --symbol-file <file>  symbol names to disassemble with
+  -I, --include-path <dir>  search <dir> for .include files
+  -D, --define NAME[=VALUE] define a symbol for .if and the program
+  -O, --optimize            remove instructions that cannot affect the result
+      --raw-alu             accept %abbbbbb comp fields
+      --var-base <address>  first RAM address for variables (16)
+      --var-limit <address> last RAM address for variables (16383)
+      --strict              make likely typos errors instead of warnings
+      --werror              make every warning an error
+  -q, --quiet               do not print warnings
+      --error-limit <n>     stop after <n> errors, 0 for no limit (50)
+  -h, --help                print this help
+      --version             print the version";
+
+// the name standing for stdin or stdout in place of a file
+const STANDARD_STREAM: &str = "-";
+
+enum Mode {
+  Assemble,
+  Disassemble(Format),
+  Link,
+  Format,
+}
+
+// one output to produce: a file assembled or disassembled, or every object file linked
+struct Job {
+  mode: Mode,
+  input_filenames: Vec<String>,
   output_filename: String,
+  listing_filename: Option<String>,
+  symbols_filename: Option<(String, SymbolFormat)>,
+}
+
+pub struct Config {
+  jobs: Vec<Job>,
+  // printed instead of doing anything, for --help and --version
+  message: Option<String>,
+  symbol_filename: Option<String>,
+  include_paths: Vec<PathBuf>,
+  defines: Vec<(String, u16)>,
+  raw_alu: bool,
+  optimize: bool,
+  object: bool,
+  strict: bool,
+  check: bool,
+  format: Format,
+  variable_base: u16,
+  variable_limit: u16,
+  werror: bool,
+  quiet: bool,
+  error_limit: usize,
+}
+
+enum SymbolFormat {
+  Sym,
+  Json,
 }
 
 impl Config {
   pub fn new (args: &[String]) -> Result<Config, &str> {
-      if args.len() < 2 {
-          return Err("not enough arguments");
-      }
+      let mut input_filenames = Vec::new();
+      let mut output_filename = None;
+      let mut message = None;
+      let mut symbol_filename = None;
+      let mut listing = false;
+      let mut symbols = None;
+      let mut include_paths = Vec::new();
+      let mut defines = Vec::new();
+      let mut raw_alu = false;
+      let mut optimize = false;
+      let mut object = false;
+      let mut strict = false;
+      let mut fmt = false;
+      let mut check = false;
+      let mut format = None;
+      let mut variable_base = DEFAULT_VARIABLE_BASE;
+      let mut variable_limit = DEFAULT_VARIABLE_LIMIT;
+      let mut werror = false;
+      let mut quiet = false;
+      let mut error_limit = DEFAULT_ERROR_LIMIT;
 
-      let input_filename = args[1].clone();
+      let mut args = args.iter().skip(1);
+      while let Some(arg) = args.next() {
+        // options take their value as `--name value` or `--name=value`,
+        // short ones also as `-Dvalue`
+        let short = arg.len() > 2 && (arg.starts_with("-D") || arg.starts_with("-I") || arg.starts_with("-o"));
+        let (flag, inline) = match arg.find('=') {
+          Some(index) if arg.starts_with("--") => (&arg[..index], Some(&arg[index+1..])),
+          _ if short                           => (&arg[..2], Some(&arg[2..])),
+          _                                    => (arg.as_str(), None),
+        };
 
-      let extension = Path::new(&input_filename)
-        .extension()
-        .and_then(OsStr::to_str);
+        match flag {
+          "--error-limit" => {
+            let limit = inline.or_else(|| args.next().map(String::as_str));
+            error_limit = match limit.map(str::parse) {
+              Some(Ok(limit)) => limit,
+              _               => return Err("--error-limit takes a number (0 for no limit)"),
+            };
+          },
+          "--symbol-file" => {
+            match inline.or_else(|| args.next().map(String::as_str)) {
+              Some(filename) => symbol_filename = Some(String::from(filename)),
+              None           => return Err("--symbol-file takes a file name"),
+            }
+          },
+          "-o" | "--output"             => {
+            match inline.or_else(|| args.next().map(String::as_str)) {
+              Some(filename) => output_filename = Some(String::from(filename)),
+              None           => return Err("--output takes a file name, or - for stdout"),
+            }
+          },
+          "-I" | "--include-path"       => {
+            match inline.or_else(|| args.next().map(String::as_str)) {
+              Some(dir) => include_paths.push(PathBuf::from(dir)),
+              None      => return Err("--include-path takes a directory"),
+            }
+          },
+          "-D" | "--define"             => {
+            let define = inline.or_else(|| args.next().map(String::as_str)).unwrap_or("");
 
-      if let Some(e) = extension {
-        if e != "asm" {
-          return Err("file must have .asm extension");
+            // a bare `-D NAME` is 1, enough for `.if NAME`
+            let (name, value) = match define.find('=') {
+              Some(index) => (&define[..index], parse_literal(&define[index+1..])),
+              None        => (define, Ok(1)),
+            };
+
+            match value {
+              Ok(value) if is_symbol(name) && (0..=MAX_LITERAL).contains(&value) =>
+                defines.push((String::from(name), value as u16)),
+              _ => return Err("--define takes NAME or NAME=VALUE with a value from 0 to 32767"),
+            }
+          },
+          "--var-base" | "--var-limit"  => {
+            let address = inline.or_else(|| args.next().map(String::as_str)).map(parse_literal);
+            let address = match address {
+              Some(Ok(address)) if (0..=MAX_LITERAL).contains(&address) => address as u16,
+              _ => return Err("--var-base and --var-limit take a RAM address from 0 to 32767"),
+            };
+
+            if flag == "--var-base" {
+              variable_base = address;
+            } else {
+              variable_limit = address;
+            }
+          },
+          "--format"                    => {
+            format = match inline.or_else(|| args.next().map(String::as_str)).and_then(Format::from_name) {
+              Some(format) => Some(format),
+              None         => return Err("--format takes hack, bin, bin-le, ihex, readmemb, readmemh or logisim"),
+            };
+          },
+          "--listing"                   => listing = true,
+          "--raw-alu"                   => raw_alu = true,
+          "-O" | "--optimize"           => optimize = true,
+          "-c" | "--object"             => object = true,
+          "--strict"                    => strict = true,
+          "--fmt"                       => fmt = true,
+          "--check"                     => check = true,
+          "--werror"                    => werror = true,
+          "-q" | "--quiet"              => quiet = true,
+          "-h" | "--help"               => message = Some(String::from(USAGE)),
+          "--version"                   => {
+            message = Some(format!("hack_assembler {}", env!("CARGO_PKG_VERSION")));
+          },
+          "--symbols"                   => {
+            symbols = match inline {
+              None | Some("sym") => Some(SymbolFormat::Sym),
+              Some("json")       => Some(SymbolFormat::Json),
+              Some(_)            => return Err("--symbols takes `sym` or `json`"),
+            };
+          },
+          STANDARD_STREAM               => input_filenames.push(arg.clone()),
+          _ if flag.starts_with('-')    => return Err("unknown option"),
+          _                             => input_filenames.push(arg.clone()),
         }
-      } else {
-        return Err("no file extension");
       }
 
-      let filename_no_ext = Path::new(&input_filename)
-        .file_stem()
+      let extension = |filename: &str| Path::new(filename)
+        .extension()
         .and_then(OsStr::to_str)
-        .unwrap();
+        .map(String::from);
 
-      let output_filename = String::from(filename_no_ext) + ".hack";
+      // stdin holds assembly; machine code in any format is disassembled,
+      // and --format can say which one when the extension is ambiguous
+      let mut modes = Vec::new();
+      for filename in &input_filenames {
+        let mode = match extension(filename).as_deref() {
+          _ if fmt && filename == STANDARD_STREAM => Mode::Format,
+          _ if filename == STANDARD_STREAM => Mode::Assemble,
+          Some("asm") if fmt => Mode::Format,
+          _ if fmt        => return Err("--fmt only applies to .asm files"),
+          Some("asm")     => Mode::Assemble,
+          Some("hobj")    => Mode::Link,
+          Some(extension) => match format.or_else(|| Format::from_extension(extension)) {
+            Some(format) => Mode::Disassemble(format),
+            None         => return Err("files must have .asm, .hobj or a machine code extension such as .hack"),
+          },
+          None            => return Err("no file extension"),
+        };
+
+        modes.push(mode);
+      }
+
+      if message.is_some() {
+        modes.clear();
+      } else if modes.is_empty() {
+        return Err("no input files; use - to read from stdin");
+      }
+
+      // object files are all linked together, everything else is one file at a time
+      let linking = modes.iter().filter(|mode| matches!(mode, Mode::Link)).count();
+      if linking > 0 && linking < modes.len() {
+        return Err("only .hobj files can be linked together");
+      }
+
+      if variable_base > variable_limit {
+        return Err("--var-base must not be above --var-limit");
+      }
+
+      if object && modes.iter().any(|mode| !matches!(mode, Mode::Assemble)) {
+        return Err("--object only applies to .asm input");
+      }
+
+      if check && !fmt {
+        return Err("--check only applies with --fmt");
+      }
+
+      if fmt && (object || listing || symbols.is_some() || format.is_some()) {
+        return Err("--fmt does not take --object, --format, --listing or --symbols");
+      }
+
+      if object && format.is_some() {
+        return Err("--format does not apply to object files");
+      }
+
+      let outputs = if linking > 0 { 1 } else { modes.len() };
+      if output_filename.is_some() && outputs > 1 {
+        return Err("--output needs a single input, or only .hobj files to link");
+      }
+
+      let format = format.unwrap_or(Format::Hack);
+      let mut jobs = Vec::new();
+      for (mode, input_filename) in modes.into_iter().zip(&input_filenames) {
+        if let (Mode::Link, Some(_)) = (&mode, jobs.first()) {
+          continue;
+        }
+
+        let input = Path::new(input_filename);
+
+        // outputs go next to the input; disassembly must not clobber the .asm
+        // the machine code may have come from
+        let output_filename = match (&output_filename, &mode) {
+          (Some(output_filename), _)                    => output_filename.clone(),
+          _ if input_filename == STANDARD_STREAM        => String::from(STANDARD_STREAM),
+          (None, Mode::Assemble) if object              => path_string(input.with_extension("hobj")),
+          (None, Mode::Assemble) | (None, Mode::Link)   => path_string(input.with_extension(format.extension())),
+          (None, Mode::Disassemble(_))                  => path_string(input.with_extension("dis.asm")),
+          // formatting rewrites the file in place
+          (None, Mode::Format)                          => input_filename.clone(),
+        };
+
+        // listings and symbol tables go next to the output, or the input when that is stdout
+        let side_file = |extension: &str| {
+          let base = match (output_filename.as_str(), input_filename.as_str()) {
+            (STANDARD_STREAM, STANDARD_STREAM) => return Err("--listing and --symbols need an input or output file"),
+            (STANDARD_STREAM, input_filename)  => input_filename,
+            (output_filename, _)               => output_filename,
+          };
+
+          Ok(path_string(Path::new(base).with_extension(extension)))
+        };
+
+        let listing_filename = match (listing, &mode) {
+          (true, Mode::Assemble) if object => return Err("--listing does not apply to object files"),
+          (true, Mode::Assemble)           => Some(side_file("lst")?),
+          (true, _)                        => return Err("--listing only applies to .asm input"),
+          (false, _)                       => None,
+        };
+
+        // a linked program has symbols too, an object file only has them once linked
+        let symbols_filename = match (&symbols, &mode) {
+          (Some(_), Mode::Assemble) if object        => return Err("--symbols does not apply to object files"),
+          (Some(_), Mode::Disassemble(_))            => return Err("--symbols only applies to .asm and .hobj input"),
+          (Some(SymbolFormat::Sym), _)               => Some((side_file("sym")?, SymbolFormat::Sym)),
+          (Some(SymbolFormat::Json), _)              => Some((side_file("sym.json")?, SymbolFormat::Json)),
+          (None, _)                                  => None,
+        };
+
+        let input_filenames = match mode {
+          Mode::Link => input_filenames.clone(),
+          _          => vec![input_filename.clone()],
+        };
+
+        jobs.push(Job {
+          mode,
+          input_filenames,
+          output_filename,
+          listing_filename,
+          symbols_filename,
+        });
+      }
 
       Ok(Config {
-        input_filename,
-        output_filename,
+        jobs,
+        message,
+        symbol_filename,
+        include_paths,
+        defines,
+        raw_alu,
+        optimize,
+        object,
+        strict,
+        check,
+        format,
+        variable_base,
+        variable_limit,
+        werror,
+        quiet,
+        error_limit,
       })
   }
 }
 
+fn path_string(path: PathBuf) -> String {
+  path.to_string_lossy().into_owned()
+}
+
+/// Settings for [`assemble`].
+#[derive(Debug, Clone)]
+pub struct Options {
+  /// Name shown for the source in diagnostics.
+  pub file_name: String,
+  /// Directories searched for `.include` files not found next to the including file.
+  pub include_paths: Vec<PathBuf>,
+  /// Symbols given values up front, as with `-D NAME=VALUE`; also what
+  /// `.if` and `.ifdef` test.
+  pub defines: Vec<(String, u16)>,
+  /// Accept `%abbbbbb` comp fields that set the ALU control bits directly.
+  pub raw_alu: bool,
+  /// Run the peephole optimizer, which drops instructions that cannot
+  /// affect the result. Programs that jump anywhere but a label, or work
+  /// out addresses from labels as in `@LOOP+3`, are left as written.
+  pub optimize: bool,
+  /// Report variables that look like misspelt labels as errors rather
+  /// than warnings.
+  pub strict: bool,
+  /// The first RAM address given to data blocks and variables.
+  pub variable_base: u16,
+  /// The last RAM address data blocks and variables may use; anything past
+  /// it is an error.
+  pub variable_limit: u16,
+  /// Stop after this many errors; 0 means no limit.
+  pub error_limit: usize,
+}
+
+impl Default for Options {
+  fn default() -> Options {
+    Options {
+      file_name: String::from("<input>"),
+      include_paths: Vec::new(),
+      defines: Vec::new(),
+      raw_alu: false,
+      optimize: false,
+      strict: false,
+      variable_base: DEFAULT_VARIABLE_BASE,
+      variable_limit: DEFAULT_VARIABLE_LIMIT,
+      error_limit: DEFAULT_ERROR_LIMIT,
+    }
+  }
+}
+
+/// The result of assembling a program.
+#[derive(Debug, Clone)]
+pub struct Program {
+  /// One machine word per ROM address.
+  pub words: Vec<u16>,
+  /// Where in the source each word came from, indexed like `words`.
+  pub locations: Vec<Span>,
+  /// Predefined symbols, labels and the variables allocated for this program.
+  pub symbols: SymbolTable,
+  /// Warnings found along the way; assembly only fails on errors.
+  pub warnings: Diagnostics,
+  /// The input and every file it included; `Span::file` indexes into these.
+  pub sources: Sources,
+  /// Words the optimizer removed; always 0 unless `Options::optimize` is set.
+  pub words_saved: usize,
+}
+
+/// Assemble Hack source held in memory.
+///
+/// Every problem in the source is reported at once: on failure the returned
+/// diagnostics hold all errors (and warnings) found, up to the error limit.
+///
+/// `.include` paths are resolved relative to `options.file_name`, then
+/// against `options.include_paths` in order.
+pub fn assemble(source: &str, options: &Options) -> Result<Program, Diagnostics> {
+  assemble_with(source, options, false).map(|(program, _)| program)
+}
+
+/// Assemble Hack source into an object file, to be combined with others by [`link`].
+///
+/// Labels named by `.export` can be used by other objects, which declare them
+/// with `.extern`. Variables are allocated by the linker, so data blocks are
+/// not allowed, and expressions can only add a constant to a label, an extern
+/// or a variable.
+pub fn assemble_object(source: &str, options: &Options) -> Result<Object, Diagnostics> {
+  let (program, mut linkage) = assemble_with(source, options, true)?;
+
+  let words = program.words
+    .iter()
+    .enumerate()
+    .map(|(address, word)| linkage.relocations.remove(&address).unwrap_or(ObjectWord::Absolute(*word)))
+    .collect();
+
+  Ok(Object {
+    words,
+    locations: program.locations,
+    exports: linkage.exports,
+    warnings: program.warnings,
+  })
+}
+
+fn assemble_with(source: &str, options: &Options, object: bool) -> Result<(Program, Linkage), Diagnostics> {
+  let (mut program, linkage, diagnostics) = analyze(source, options, object);
+  if diagnostics.has_errors() {
+    return Err(diagnostics);
+  }
+
+  program.warnings = diagnostics;
+  Ok((program, linkage))
+}
+
+// assemble as far as possible in spite of errors, for tools such as the
+// language server that want the symbols and words along with the diagnostics
+fn analyze(source: &str, options: &Options, object: bool) -> (Program, Linkage, Diagnostics) {
+  let mut diagnostics = Diagnostics::new(options.error_limit);
+  let mut sources = Sources::default();
+  sources.add(&options.file_name, String::from(source), None);
+
+  let mut assembly = Assembly::new(&mut sources, options, &mut diagnostics);
+  let words_saved = if options.optimize { optimize(&mut assembly.instructions) } else { 0 };
+
+  let assembler = Assembler::new(options, object);
+  let (mut program, linkage) = assembler.translate(&assembly.instructions, &mut diagnostics);
+
+  let diagnostics = diagnostics.with_sources(&sources);
+  program.sources = sources;
+  program.words_saved = words_saved;
+  (program, linkage, diagnostics)
+}
+
 struct Assembly {
-  instructions: Vec<Instruction>,
+  instructions: Vec<(Span, Instruction)>,
 }
 
 impl Assembly {
-  fn new(input: String) -> Assembly {
+  // bad lines are reported and skipped so the rest of the file is still checked
+  fn new(sources: &mut Sources, options: &Options, diagnostics: &mut Diagnostics) -> Assembly {
     let mut instructions = Vec::new();
 
-    input.split('\n').for_each(|line| {
-      let instruction = match line.find("//") {
-        Some(comment_index) => line.get(..comment_index).unwrap().trim(),
-        None                => line.trim(),
-      };
+    for line in preprocess(sources, options, diagnostics) {
+      if diagnostics.is_full() {
+        break;
+      }
 
-      if !instruction.is_empty() {
-        instructions.push(Instruction::get(instruction));
+      match Instruction::get(&line.text, line.span) {
+        Ok(parsed) => {
+          if let Instruction::CInstruction { dest: Dest::Null, jump: Jump::Null, .. } = parsed {
+            let kind = ErrorKind::NoEffect(line.text.clone());
+            diagnostics.push(line.locate(AssembleError::new(kind, line.span)));
+          }
+
+          // undocumented ALU settings are opt-in so a typo does not assemble to nonsense
+          if let Instruction::CInstruction { comp: comp @ Comp::Raw(_), .. } = parsed {
+            if !options.raw_alu {
+              let kind = ErrorKind::RawAluDisabled(comp.to_string());
+              diagnostics.push(line.locate(AssembleError::new(kind, line.span)));
+            }
+          }
+
+          instructions.push((line.span, parsed));
+        },
+        Err(e)     => diagnostics.push(line.locate(e)),
       }
-    });
+    }
 
     Assembly { instructions }
   }
 }
 
 struct Assembler {
-  symbol_table: HashMap<String, i32>,
+  symbol_table: SymbolTable,
+  // whether the program is assembled into an object file for the linker
+  object: bool,
+  strict: bool,
+  variable_base: u16,
+  variable_limit: u16,
+}
+
+// what an object file needs besides its words
+#[derive(Default)]
+struct Linkage {
+  exports: Vec<(String, u16, Span)>,
+  // the words the linker fills in, by address
+  relocations: HashMap<usize, ObjectWord>,
+}
+
+// hands out RAM to data blocks and variables, in the order they come up
+struct Ram {
+  next: u32,
+  limit: u16,
+  // one error or warning says all there is to say
+  full: bool,
+  warned: bool,
+}
+
+impl Ram {
+  fn new(base: u16, limit: u16) -> Ram {
+    Ram { next: u32::from(base), limit, full: false, warned: false }
+  }
+
+  // the address of `size` words for `name`; `span` is where to report it
+  // if the words do not fit or run into the VM stack
+  fn allocate(&mut self, name: &str, size: u16, span: Span, diagnostics: &mut Diagnostics) -> u16 {
+    let address = self.next;
+    let end = address + u32::from(size) - 1;
+
+    if end > u32::from(self.limit) {
+      if !self.full {
+        let kind = ErrorKind::RamOverflow(String::from(name), self.limit);
+        diagnostics.push(AssembleError::new(kind, span));
+        self.full = true;
+      }
+    } else if !self.warned && address <= VM_STACK_END && end >= VM_STACK_START {
+      let kind = ErrorKind::StackOverlap(String::from(name), address as u16);
+      diagnostics.push(AssembleError::new(kind, span));
+      self.warned = true;
+    }
+
+    self.next = end + 1;
+    address as u16
+  }
+}
+
+// the symbols every program starts with
+fn predefined_symbols() -> SymbolTable {
+  let mut symbol_table = SymbolTable::default();
+
+  symbol_table.predefine("R0", 0);
+  symbol_table.predefine("R1", 1);
+  symbol_table.predefine("R2", 2);
+  symbol_table.predefine("R3", 3);
+  symbol_table.predefine("R4", 4);
+  symbol_table.predefine("R5", 5);
+  symbol_table.predefine("R6", 6);
+  symbol_table.predefine("R7", 7);
+  symbol_table.predefine("R8", 8);
+  symbol_table.predefine("R9", 9);
+  symbol_table.predefine("R10", 10);
+  symbol_table.predefine("R11", 11);
+  symbol_table.predefine("R12", 12);
+  symbol_table.predefine("R13", 13);
+  symbol_table.predefine("R14", 14);
+  symbol_table.predefine("R15", 15);
+  symbol_table.predefine("SP", 0);
+  symbol_table.predefine("LCL", 1);
+  symbol_table.predefine("ARG", 2);
+  symbol_table.predefine("THIS", 3);
+  symbol_table.predefine("THAT", 4);
+  symbol_table.predefine("SCREEN", 16384);
+  symbol_table.predefine("KBD", 24576);
+
+  symbol_table
 }
 
 impl Assembler {
-  fn new() -> Assembler {
-    let mut symbol_table: HashMap<String, i32> = HashMap::new();
-
-    symbol_table.insert(String::from("R0"), 0);
-    symbol_table.insert(String::from("R1"), 1);
-    symbol_table.insert(String::from("R2"), 2);
-    symbol_table.insert(String::from("R3"), 3);
-    symbol_table.insert(String::from("R4"), 4);
-    symbol_table.insert(String::from("R5"), 5);
-    symbol_table.insert(String::from("R6"), 6);
-    symbol_table.insert(String::from("R7"), 7);
-    symbol_table.insert(String::from("R8"), 8);
-    symbol_table.insert(String::from("R9"), 9);
-    symbol_table.insert(String::from("R10"), 10);
-    symbol_table.insert(String::from("R11"), 11);
-    symbol_table.insert(String::from("R12"), 12);
-    symbol_table.insert(String::from("R13"), 13);
-    symbol_table.insert(String::from("R14"), 14);
-    symbol_table.insert(String::from("R15"), 15);
-    symbol_table.insert(String::from("SP"), 0);
-    symbol_table.insert(String::from("LCL"), 1);
-    symbol_table.insert(String::from("ARG"), 2);
-    symbol_table.insert(String::from("THIS"), 3);
-    symbol_table.insert(String::from("THAT"), 4);
-    symbol_table.insert(String::from("SCREEN"), 16384);
-    symbol_table.insert(String::from("KBD"), 24576);
-
-    Assembler { symbol_table }
-  }
-
-  fn translate(&self, instructions: &Vec<Instruction>) -> String {
+  fn new(options: &Options, object: bool) -> Assembler {
+    let mut symbol_table = predefined_symbols();
+
+    for (name, value) in &options.defines {
+      symbol_table.define(name, *value);
+    }
+
+    Assembler {
+      symbol_table,
+      object,
+      strict: options.strict,
+      variable_base: options.variable_base,
+      variable_limit: options.variable_limit,
+    }
+  }
+
+  fn translate(&self, instructions: &[(Span, Instruction)], diagnostics: &mut Diagnostics) -> (Program, Linkage) {
     let mut symbol_table = self.symbol_table.clone();
 
-    // add labels to symbol table
-    let mut line_number = 0;
-    instructions.iter().for_each(|instruction| {
+    // data blocks get RAM ahead of the variables, and the prologue at
+    // ROM 0 stores their initial values before the program starts
+    let mut ram = Ram::new(self.variable_base, self.variable_limit);
+    let mut prologue = Vec::new();
+    for (span, instruction) in instructions {
+      if let Instruction::Data { name, init } = instruction {
+        if self.object {
+          diagnostics.push(AssembleError::new(ErrorKind::NotInObject(name.clone()), *span));
+          continue;
+        }
+
+        match symbol_table.get(name) {
+          Some(Symbol { span: Some(first), .. }) => {
+            let kind = ErrorKind::DuplicateSymbol(name.clone(), Some(first.line));
+            diagnostics.push(AssembleError::new(kind, *span));
+            continue;
+          },
+          Some(_) => {
+            let kind = ErrorKind::DuplicateSymbol(name.clone(), None);
+            diagnostics.push(AssembleError::new(kind, *span));
+            continue;
+          },
+          None => (),
+        }
+
+        let size = match init {
+          DataInit::Reserved(size) => *size,
+          DataInit::Values(values) => values.len() as u16,
+        };
+
+        let address = ram.allocate(name, size, *span, diagnostics);
+        symbol_table.insert(name, SymbolKind::Data, address, *span);
+
+        if let DataInit::Values(values) = init {
+          for (i, (expr, offset, len)) in values.iter().enumerate() {
+            let value_span = span.sub(*offset, *len);
+
+            match expr.eval(&|name| symbol_table.address(name)) {
+              Ok(value) if (-0x8000..=0xFFFF).contains(&value) => {
+                let words = store_word(address.wrapping_add(i as u16), value as u16);
+                prologue.extend(words.into_iter().map(|word| (word, *span)));
+              },
+              Ok(value)  => {
+                let kind = ErrorKind::WordOutOfRange(expr.to_string(), value);
+                diagnostics.push(AssembleError::new(kind, value_span));
+              },
+              Err(error) => diagnostics.push(expr_error(error, value_span)),
+            }
+          }
+        }
+      }
+    }
+
+    // add labels to symbol table. a `.name` label is local to the last global
+    // label written in the source and is stored as `GLOBAL.name`
+    let mut line_number = prologue.len() as u16;
+    let mut scope = String::new();
+    let mut scopes = Vec::with_capacity(instructions.len());
+    let mut numeric_labels: HashMap<u32, Vec<(usize, u16)>> = HashMap::new();
+    for (index, (span, instruction)) in instructions.iter().enumerate() {
       match instruction {
         Instruction::LInstruction(label) => {
+          let label_span = span.sub(1, label.chars().count());
+
+          // labels made up by macro expansions do not start a scope
+          if !label.starts_with('.') && !span.expanded {
+            scope = label.clone();
+          }
+
+          let label = &if label.starts_with('.') && !scope.is_empty() {
+            format!("{}{}", scope, label)
+          } else {
+            label.clone()
+          };
+
+          // the first definition wins so later references still resolve somewhere
+          match symbol_table.get(label) {
+            Some(Symbol { kind: SymbolKind::Label, span: Some(first), .. }) => {
+              let kind = ErrorKind::DuplicateLabel(label.clone(), first.line);
+              diagnostics.push(AssembleError::new(kind, label_span));
+              continue;
+            },
+            Some(Symbol { kind: SymbolKind::Defined, .. }) => {
+              let kind = ErrorKind::LabelIsDefined(label.clone());
+              diagnostics.push(AssembleError::new(kind, label_span));
+              continue;
+            },
+            Some(Symbol { kind: SymbolKind::Data, span: Some(first), .. }) => {
+              let kind = ErrorKind::DuplicateSymbol(label.clone(), Some(first.line));
+              diagnostics.push(AssembleError::new(kind, label_span));
+              continue;
+            },
+            Some(Symbol { kind: SymbolKind::Predefined, .. }) => {
+              let kind = ErrorKind::DuplicateSymbol(label.clone(), None);
+              diagnostics.push(AssembleError::new(kind, label_span));
+              continue;
+            },
+            _ => (),
+          }
+
           symbol_table.insert(
-            label.clone(),
-            line_number
+            label,
+            SymbolKind::Label,
+            line_number,
+            label_span,
           );
+        },
+        Instruction::NumericLabel(label) => {
+          numeric_labels.entry(*label).or_default().push((index, line_number));
+        },
+        // past the end of ROM addresses mean nothing, the overflow is reported below
+        _ => line_number = line_number.saturating_add(instruction.size()),
+      }
+
+      scopes.push(scope.clone());
+    }
+
+    // exports must name labels, and so must externs unless the linker resolves them
+    let mut linkage = Linkage::default();
+    let mut externs = HashSet::new();
+    for (span, instruction) in instructions {
+      let operand = |name: &str| span.sub(span.len - name.chars().count(), name.chars().count());
+      let label = |name: &str| match symbol_table.get(name) {
+        Some(Symbol { kind: SymbolKind::Label, address, .. }) => Some(*address),
+        _                                                   => None,
+      };
 
+      match instruction {
+        Instruction::Export(name) => match label(name) {
+          Some(_) if linkage.exports.iter().any(|(exported, _, _)| exported == name) => (),
+          Some(address) => linkage.exports.push((name.clone(), address, *span)),
+          None          => diagnostics.push(AssembleError::new(ErrorKind::ExportNotLabel(name.clone()), operand(name))),
+        },
+        Instruction::Extern(name) if self.object => {
+          externs.insert(name.as_str());
+        },
+        Instruction::Extern(name) if label(name).is_none() => {
+          diagnostics.push(AssembleError::new(ErrorKind::UndefinedExtern(name.clone()), operand(name)));
         },
-        _ => line_number += 1,
+        _ => (),
       }
-    });
+    }
 
-    // translate instructions
-    let mut output = String::new();
-    let mut curr_ram_loc = 16;
-    instructions.iter().for_each(|instruction| {
-      let mut translation = String::new();
-
-      if let Instruction::AInstruction(a_instruction) = instruction {
-        match a_instruction {
-          AInstruction::Num(binary) => translation = format!("{}\n", binary),
-          AInstruction::Var(name)   => {                                
-            if let Some(value) = symbol_table.get(name) {
-              translation = format!("{:016b}\n", value);
+    // in an object file, ROM addresses move with the module. a label at the
+    // very end of a full ROM is past what an A-instruction holds, and would
+    // turn into a C-instruction
+    let object = self.object;
+    let rom_address = move |address: u16, label: &str, operand: Span, diagnostics: &mut Diagnostics| {
+      if i64::from(address) > MAX_LITERAL {
+        let kind = ErrorKind::LabelOutOfRange(String::from(label), address);
+        diagnostics.push(AssembleError::new(kind, operand));
+        return (0, None);
+      }
+
+      if object {
+        (address, Some(ObjectWord::Label(i64::from(address))))
+      } else {
+        (address, None)
+      }
+    };
+
+    // the value of an A-instruction operand, allocating variables as they come up,
+    // along with what the linker has to fill in for it; `operand` covers its text
+    // and `index` is the position of its instruction
+    let mut operand_value = |a_instruction: &AInstruction, operand: Span, scope: &str, index: usize| {
+      match a_instruction {
+        AInstruction::Num(value) => (*value, None),
+        AInstruction::Var(name)  => {
+          let name = &resolve_local(&symbol_table, scope, name);
+
+          // a `.name` that is no local label cannot be a variable either
+          if name.starts_with('.') && !symbol_table.contains(name) {
+            let kind = ErrorKind::UndefinedLocalLabel(format!("{}{}", scope, name));
+            diagnostics.push(AssembleError::new(kind, operand));
+            return (0, None);
+          }
+
+          if !symbol_table.contains(name) && self.object {
+            let word = if externs.contains(name.as_str()) {
+              ObjectWord::Extern(name.clone(), 0)
             } else {
-              symbol_table.insert(
-                name.clone(),
-                curr_ram_loc,
-              );
+              ObjectWord::Variable(name.clone(), 0)
+            };
+
+            return (0, Some(word));
+          }
+
+          if !symbol_table.contains(name) {
+            let address = ram.allocate(name, 1, operand, diagnostics);
+            symbol_table.insert(
+              name,
+              SymbolKind::Variable,
+              address,
+              operand,
+            );
+          }
+
+          symbol_table.mark_use(name, operand);
+          match symbol_table.get(name) {
+            Some(Symbol { kind: SymbolKind::Label, address, .. }) => rom_address(*address, name, operand, diagnostics),
+            _ => (symbol_table.address(name).unwrap(), None),
+          }
+        },
+        AInstruction::Expr(expr) => {
+          expr.symbols().iter().for_each(|(name, offset)| {
+            let span = operand.sub(*offset, name.chars().count());
+            symbol_table.mark_use(&resolve_local(&symbol_table, scope, name), span);
+          });
+
+          let resolve = |name: &str| resolve_local(&symbol_table, scope, name);
+          let lookup = |name: &str| symbol_table.address(&resolve(name));
 
-              translation = format!("{:016b}\n", curr_ram_loc);
+          let value = if self.object {
+            // only what a label, extern or variable adds up to is left to the linker
+            let relocatable = |name: &str| {
+              symbol_table.get(&resolve(name)).is_none_or(|symbol| symbol.kind == SymbolKind::Label)
+            };
 
-              curr_ram_loc += 1;
+            match expr.relocation(&relocatable, &lookup) {
+              Ok((Some(name), addend)) => {
+                let name = resolve(&name);
+                let word = match symbol_table.address(&name) {
+                  Some(address)                           => ObjectWord::Label(i64::from(address) + addend),
+                  None if externs.contains(name.as_str()) => ObjectWord::Extern(name, addend),
+                  None                                    => ObjectWord::Variable(name, addend),
+                };
+
+                return (0, Some(word));
+              },
+              Ok((None, value)) => Ok(value),
+              Err(error)        => Err(error),
             }
-          },
-        }
-      } else if let Instruction::CInstruction(binary) = instruction {
-        translation = format!("{}\n", binary);
+          } else {
+            // labels are all known by now, variables only once allocated
+            expr.eval(&lookup)
+          };
+
+          match value {
+            Ok(value) if (0..=MAX_LITERAL).contains(&value) => (value as u16, None),
+            Ok(value) => {
+              let kind = ErrorKind::ExpressionOutOfRange(expr.to_string(), value);
+              diagnostics.push(AssembleError::new(kind, operand));
+              (0, None)
+            },
+            Err(error) => {
+              diagnostics.push(expr_error(error, operand));
+              (0, None)
+            },
+          }
+        },
+        AInstruction::Numeric { label, forward } => {
+          let defined = numeric_labels.get(label).map_or(&[][..], Vec::as_slice);
+          let target = if *forward {
+            defined.iter().find(|(at, _)| *at > index)
+          } else {
+            defined.iter().rev().find(|(at, _)| *at < index)
+          };
+
+          match target {
+            Some((_, address)) => {
+              let label = format!("{}{}", label, if *forward { 'f' } else { 'b' });
+              rom_address(*address, &label, operand, diagnostics)
+            },
+            None               => {
+              let kind = ErrorKind::UndefinedNumericLabel(*label, *forward);
+              diagnostics.push(AssembleError::new(kind, operand));
+              (0, None)
+            },
+          }
+        },
       }
+    };
+
+    // translate instructions
+    let (mut words, mut locations): (Vec<u16>, Vec<Span>) = prologue.into_iter().unzip();
+    for (index, ((span, instruction), scope)) in instructions.iter().zip(&scopes).enumerate() {
+      let operand = |offset: usize| span.sub(offset, span.len - offset);
 
-      output.push_str(&translation);
-    });
+      // an operand always ends up in the first word of its instruction
+      let (instruction_words, relocation) = match instruction {
+        Instruction::AInstruction(a_instruction) => {
+          let (value, relocation) = operand_value(a_instruction, operand(1), scope, index);
+          (vec![value], relocation)
+        },
+        Instruction::CInstruction { dest, comp, jump } => (vec![encode_c(*dest, *comp, *jump)], None),
+        Instruction::Pseudo(pseudo) => {
+          let mut relocation = None;
+          let words = pseudo.expand(&mut |a_instruction, offset| {
+            let (value, operand_relocation) = operand_value(a_instruction, operand(offset), scope, index);
+            relocation = operand_relocation;
+            value
+          });
+          (words, relocation)
+        },
+        Instruction::LInstruction(_)
+        | Instruction::NumericLabel(_)
+        | Instruction::Data { .. }
+        | Instruction::Export(_)
+        | Instruction::Extern(_) => continue,
+      };
 
-    output
+      if let Some(relocation) = relocation {
+        linkage.relocations.insert(words.len(), relocation);
+      }
+
+      locations.extend(instruction_words.iter().map(|_| *span));
+      words.extend(instruction_words);
+    }
+
+    if let Some(span) = locations.get(ROM_SIZE) {
+      diagnostics.push(AssembleError::new(ErrorKind::RomOverflow(words.len()), *span));
+    }
+
+    // in an object file variables are only known once linked
+    if !self.object {
+      check_variables(instructions, &scopes, &symbol_table, self.strict, diagnostics);
+    }
+
+    let program = Program {
+      words,
+      locations,
+      symbols: symbol_table,
+      warnings: Diagnostics::new(0),
+      sources: Sources::default(),
+      words_saved: 0,
+    };
+
+    (program, linkage)
   }
 }
 
-#[derive(Clone, PartialEq)]
-enum Instruction {
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+pub(crate) enum Instruction {
   AInstruction(AInstruction),
   LInstruction(String),
-  CInstruction(String),
+  // `1:`, a label that may be defined any number of times
+  NumericLabel(u32),
+  CInstruction { dest: Dest, comp: Comp, jump: Jump },
+  // `.data`, `.word` or `.string`, a named block of RAM
+  Data { name: String, init: DataInit },
+  // `.export NAME`, a label other object files may use
+  Export(String),
+  // `.extern NAME`, a label another object file defines
+  Extern(String),
+  Pseudo(Pseudo),
+}
+
+// mnemonics that stand for a short sequence of instructions. `offset` is
+// the char offset of the operand in the line, for diagnostics
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Pseudo {
+  // `LOAD D, value` (or `A`, `AD`), for any 16-bit value
+  Load { dest: Dest, value: AInstruction, offset: usize },
+  // `JMP target`, or a conditional jump on D such as `JZ D, target`
+  Jump { comp: Comp, jump: Jump, target: AInstruction, offset: usize },
+  // `PUSHD`: push D onto the stack pointed to by `SP`
+  PushD,
+  // `POPD`: pop the top of the stack into D
+  PopD,
+}
+
+// conditional jumps on D, by pseudo-instruction mnemonic
+const CONDITIONAL_JUMPS: [(&str, Jump); 6] = [
+  ("JZ" , Jump::Jeq),
+  ("JNZ", Jump::Jne),
+  ("JGT", Jump::Jgt),
+  ("JGE", Jump::Jge),
+  ("JLT", Jump::Jlt),
+  ("JLE", Jump::Jle),
+];
+
+impl Pseudo {
+  fn is_mnemonic(mnemonic: &str) -> bool {
+    matches!(mnemonic, "LOAD" | "JMP" | "PUSHD" | "POPD")
+      || CONDITIONAL_JUMPS.iter().any(|(name, _)| *name == mnemonic)
+  }
+
+  // `tokens` are those of `s`, less whitespace
+  fn get(s: &str, tokens: &[Token], span: Span) -> Result<Pseudo, AssembleError> {
+    let mnemonic = tokens[0].text;
+    let operands = &tokens[1..];
+    let malformed = |usage: &str| AssembleError::new(ErrorKind::MalformedPseudo(String::from(usage)), span);
+
+    // an operand runs to the end of the line; its text, char offset and span
+    let operand = |tokens: &[Token]| {
+      let range = tokens[0].span.start..s.len();
+      (&s[range.clone()], s[..range.start].chars().count(), sub_span(span, s, range))
+    };
+
+    // the register before the `,` in `LOAD` and conditional jumps, and the operand after it
+    let split = || {
+      let comma = operands.iter().position(|token| token.is_punct(","))?;
+      let (register, value) = (&operands[..comma], &operands[comma + 1..]);
+
+      if value.is_empty() {
+        return None;
+      }
+      Some((&s[token_range(register, 0)], value))
+    };
+
+    match mnemonic {
+      "PUSHD" if operands.is_empty() => Ok(Pseudo::PushD),
+      "POPD" if operands.is_empty()  => Ok(Pseudo::PopD),
+      "PUSHD" | "POPD"               => Err(malformed(mnemonic)),
+      "JMP" if !operands.is_empty()  => {
+        let (target, offset, target_span) = operand(operands);
+        let target = AInstruction::get(target, target_span)?;
+        Ok(Pseudo::Jump { comp: Comp::Zero, jump: Jump::Jmp, target, offset })
+      },
+      "JMP"                          => Err(malformed("JMP target")),
+      "LOAD"                         => {
+        let (dest, value) = match split() {
+          Some((register, value)) => match Dest::parse(register) {
+            Some(dest @ Dest::D) | Some(dest @ Dest::A) | Some(dest @ Dest::Ad) => (dest, value),
+            _ => return Err(malformed("LOAD D|A|AD, value")),
+          },
+          None => return Err(malformed("LOAD D|A|AD, value")),
+        };
+
+        let (value, offset, value_span) = operand(value);
+        let value = load_value(value, value_span)?;
+        Ok(Pseudo::Load { dest, value, offset })
+      },
+      _                              => {
+        let jump = CONDITIONAL_JUMPS.iter().find(|(name, _)| *name == mnemonic).map(|(_, jump)| *jump);
+        let usage = format!("{} D, target", mnemonic);
+
+        match (jump, split()) {
+          (Some(jump), Some(("D", target))) => {
+            let (target, offset, target_span) = operand(target);
+            let target = AInstruction::get(target, target_span)?;
+            Ok(Pseudo::Jump { comp: Comp::D, jump, target, offset })
+          },
+          _ => Err(malformed(&usage)),
+        }
+      },
+    }
+  }
+
+  // the words the pseudo-instruction stands for; `operand_value` gives the
+  // value of an operand at the given char offset
+  fn expand(&self, operand_value: &mut dyn FnMut(&AInstruction, usize) -> u16) -> Vec<u16> {
+    let c = |dest, comp, jump| encode_c(dest, comp, jump);
+
+    match self {
+      Pseudo::Load { dest, value: AInstruction::Num(value), .. } => load(*dest, *value),
+      // a symbol's value is not known in the first pass, so it always takes the long form
+      Pseudo::Load { dest: Dest::A, value, offset } => vec![operand_value(value, *offset)],
+      Pseudo::Load { dest, value, offset } => {
+        vec![operand_value(value, *offset), c(*dest, Comp::A, Jump::Null)]
+      },
+      Pseudo::Jump { comp, jump, target, offset } => {
+        vec![operand_value(target, *offset), c(Dest::Null, *comp, *jump)]
+      },
+      Pseudo::PushD => vec![
+        0,
+        c(Dest::Am, Comp::MPlusOne, Jump::Null),
+        c(Dest::A, Comp::AMinusOne, Jump::Null),
+        c(Dest::M, Comp::D, Jump::Null),
+      ],
+      Pseudo::PopD  => vec![
+        0,
+        c(Dest::Am, Comp::MMinusOne, Jump::Null),
+        c(Dest::D, Comp::M, Jump::Null),
+      ],
+    }
+  }
+
+  // how many words `expand` produces, known before any symbol is
+  fn len(&self) -> u16 {
+    self.expand(&mut |_, _| 0).len() as u16
+  }
+}
+
+// what a data directive puts in its block of RAM
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum DataInit {
+  // `.data NAME SIZE`: words left as they are
+  Reserved(u16),
+  // `.word` and `.string`: one value per word, with its char offset and
+  // length in the line for diagnostics
+  Values(Vec<(Expr, usize, usize)>),
 }
 
 impl Instruction {
-  fn get(s: &str) -> Instruction {
-    match s.chars().next().unwrap() {
-      '@' => {
-        let result = s.get(1..).unwrap();
-        Instruction::AInstruction(AInstruction::get(result))
+  // how many ROM words the instruction takes
+  fn size(&self) -> u16 {
+    match self {
+      Instruction::AInstruction(_) | Instruction::CInstruction { .. } => 1,
+      Instruction::Pseudo(pseudo)                                    => pseudo.len(),
+      Instruction::LInstruction(_)
+      | Instruction::NumericLabel(_)
+      | Instruction::Data { .. }
+      | Instruction::Export(_)
+      | Instruction::Extern(_)                                       => 0,
+    }
+  }
+
+  // dispatch on the first token; `s` is the code of one line, without its comment
+  fn get(s: &str, span: Span) -> Result<Instruction, AssembleError> {
+    let tokens: Vec<Token> = tokenize(s).into_iter().filter(|token| !token.is_trivia()).collect();
+
+    match tokens.first() {
+      Some(first) if first.is_punct("@")                                => {
+        let range = first.span.end..s.len();
+        let a_instruction = AInstruction::get(&s[range.clone()], sub_span(span, s, range))?;
+
+        Ok(Instruction::AInstruction(a_instruction))
       },
-      '(' => {
-        let result = s.get(1..s.len()-1).unwrap();
-        Instruction::LInstruction(String::from(result))
+      Some(first) if first.is_punct("(")                                => parse_label(s, &tokens, span),
+      Some(first) if first.kind == TokenKind::Word && first.text.starts_with('.') => parse_directive(s, &tokens, span),
+      Some(first) if is_numeric_label(first)                            => {
+        after_label(s, &tokens[1..], span)?;
+        Ok(Instruction::NumericLabel(parse_digits(&first.text[..first.text.len() - 1]).unwrap()))
       },
-      _   => {
-        let result = construct_comp_binary(s);
-        Instruction::CInstruction(result)
-      }
+      Some(first) if Pseudo::is_mnemonic(first.text)                    => Pseudo::get(s, &tokens, span).map(Instruction::Pseudo),
+      _                                                                 => parse_c_instruction(s, &tokens, span),
     }
   }
 }
 
-#[derive(Clone, PartialEq)]
-enum AInstruction {
-  Num(String),
+// `(NAME)`, where spaces may pad the name
+fn parse_label(s: &str, tokens: &[Token], span: Span) -> Result<Instruction, AssembleError> {
+  match tokens {
+    [_, name, close, rest @ ..] if is_symbol(name.text) && close.is_punct(")") => {
+      after_label(s, rest, span)?;
+      Ok(Instruction::LInstruction(String::from(name.text)))
+    },
+    _ => {
+      let kind = ErrorKind::MalformedLabel(String::from(s));
+      Err(AssembleError::new(kind, span))
+    },
+  }
+}
+
+// a label takes the whole line, so anything after it is a mistake
+fn after_label(s: &str, rest: &[Token], span: Span) -> Result<(), AssembleError> {
+  match (rest.first(), rest.last()) {
+    (Some(first), Some(last)) => {
+      let range = first.span.start..last.span.end;
+      let kind = ErrorKind::TextAfterLabel(String::from(&s[range.clone()]));
+      Err(AssembleError::new(kind, sub_span(span, s, range)))
+    },
+    _                         => Ok(()),
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum AInstruction {
+  Num(u16),
   Var(String),
+  Expr(Expr),
+  // `@1b` or `@1f`, the nearest `1:` before or after the instruction
+  Numeric { label: u32, forward: bool },
 }
 
 impl AInstruction {
-  fn get(s: &str) -> AInstruction {
-    match s.parse::<i32>() {
-      Ok(num) => {
-        let binary = format!("{:016b}", num);
-        AInstruction::Num(binary)
-      },
-      _ => {
-        let name = String::from(s);
-        AInstruction::Var(name)
-      }
+  // `span` covers the operand `s`
+  fn get(s: &str, span: Span) -> Result<AInstruction, AssembleError> {
+    if is_symbol(s) {
+      return Ok(AInstruction::Var(String::from(s)));
+    }
+
+    if s.is_empty() {
+      return Err(AssembleError::new(ErrorKind::MissingOperand, span));
+    }
+
+    // a bare `0b` is a binary literal with no digits, not a reference back to `0:`
+    if s == "0b" {
+      let kind = ErrorKind::BadLiteral(String::from(s), String::from("binary literal has no digits"));
+      return Err(AssembleError::new(kind, span).with_note(String::from("a reference back to label `0:` is written `00b`")));
+    }
+
+    let numeric = |suffix| s.strip_suffix(suffix).and_then(parse_digits);
+    if let Some(label) = numeric('b') {
+      return Ok(AInstruction::Numeric { label, forward: false });
+    }
+    if let Some(label) = numeric('f') {
+      return Ok(AInstruction::Numeric { label, forward: true });
+    }
+
+    let expr = Expr::parse(s).map_err(|error| expr_error(error, span))?;
+
+    // anything without symbols can be folded right away
+    if expr.symbols().is_empty() {
+      let kind = match expr.eval(&|_| None) {
+        Ok(num) if (0..=MAX_LITERAL).contains(&num) => return Ok(AInstruction::Num(num as u16)),
+        Ok(_) if matches!(expr, Expr::Num(_))       => ErrorKind::LiteralOutOfRange(String::from(s)),
+        Ok(num)                                     => ErrorKind::ExpressionOutOfRange(String::from(s), num),
+        Err(error)                                  => return Err(expr_error(error, span)),
+      };
+
+      return Err(AssembleError::new(kind, span));
+    }
+
+    Ok(AInstruction::Expr(expr))
+  }
+}
+
+fn expr_error((kind, offset, len): expr::ExprError, span: Span) -> AssembleError {
+  match len {
+    0 => AssembleError::new(kind, span),
+    _ => AssembleError::new(kind, span.sub(offset, len)),
+  }
+}
+
+// `.name` inside a scope means the scope's local label if there is one,
+// otherwise it is an ordinary symbol
+fn resolve_local(symbol_table: &SymbolTable, scope: &str, name: &str) -> String {
+  if name.starts_with('.') && !scope.is_empty() {
+    let qualified = format!("{}{}", scope, name);
+    if let Some(Symbol { kind: SymbolKind::Label, .. }) = symbol_table.get(&qualified) {
+      return qualified;
     }
   }
+
+  String::from(name)
 }
 
-fn construct_comp_binary(s: &str) -> String {
-  let dest = parse_dest(s);
-  let jmp = parse_jmp(s);
-  let comp = parse_comp(s);
+// the number in a numeric label or reference, e.g. the `1` of `1:` or `1b`
+fn parse_digits(s: &str) -> Option<u32> {
+  if s.chars().all(|c| c.is_ascii_digit()) {
+    s.parse().ok()
+  } else {
+    None
+  }
+}
 
-  let dest_binary = dest_to_binary(dest);
-  let comp_binary = comp_to_binary(comp);
-  let jmp_binary = jmp_to_binary(jmp);
+// symbols are letters, digits, `_`, `.`, `$` and `:`, not starting with a digit
+fn is_symbol(s: &str) -> bool {
+  match s.chars().next() {
+    Some(c) => !c.is_ascii_digit() && s.chars().all(is_symbol_char),
+    None    => false,
+  }
+}
 
-  String::from("111") + &comp_binary + &dest_binary + &jmp_binary
+// span of the byte range `range` of `s`, where `span` covers all of `s`
+fn sub_span(span: Span, s: &str, range: Range<usize>) -> Span {
+  span.sub(s[..range.start].chars().count(), s[range].chars().count())
 }
 
-fn parse_comp(s: &str) -> &str {
-  match s.find("=") {
-    Some(d_index) => {
-      match s.find(";") {
-        Some(j_index) => s.get(d_index+1..j_index).unwrap(),
-        None          => s.get(d_index+1..).unwrap(),
-      }
+// `.export NAME`, `.extern NAME` or a data directive
+fn parse_directive(s: &str, tokens: &[Token], span: Span) -> Result<Instruction, AssembleError> {
+  let directive = tokens[0].text;
+  let name = &s[token_range(&tokens[1..], s.len())];
+
+  match directive {
+    ".export" | ".extern" if !is_symbol(name) => {
+      let kind = ErrorKind::MalformedLinkage(String::from(directive));
+      Err(AssembleError::new(kind, span))
     },
-    None => {
-      match s.find(";") {
-        Some(j_index) => s.get(..j_index).unwrap(),
-        None          => s
-      }
-    }
+    ".export" => Ok(Instruction::Export(String::from(name))),
+    ".extern" => Ok(Instruction::Extern(String::from(name))),
+    _         => parse_data(s, tokens, span),
   }
 }
 
-fn parse_dest(s: &str) -> &str {
-  match s.find("=") {
-    Some(d_index) => {
-      match s.get(..d_index) {
-        Some(d) => d,
-        None    => "",
+// `.data NAME SIZE`, `.word NAME value, ...` or `.string NAME "text"`;
+// strings end with a 0 word
+fn parse_data(s: &str, tokens: &[Token], span: Span) -> Result<Instruction, AssembleError> {
+  let directive = tokens[0].text;
+  let name = tokens.get(1).filter(|token| token.kind == TokenKind::Word).map_or("", |token| token.text);
+  let operands = tokens.get(2..).unwrap_or(&[]);
+  let operands_range = token_range(operands, s.len());
+  let operands_text = &s[operands_range.clone()];
+  let operands_span = sub_span(span, s, operands_range.clone());
+
+  let malformed = |why: &str, span| Err(AssembleError::new(ErrorKind::MalformedData(String::from(why)), span));
+
+  if !matches!(directive, ".data" | ".word" | ".string") {
+    let kind = ErrorKind::UnknownDirective(String::from(directive));
+    return Err(AssembleError::new(kind, sub_span(span, s, tokens[0].span.clone())));
+  }
+
+  if !is_symbol(name) {
+    return malformed("expected a symbol name after the directive", span);
+  }
+
+  if operands.is_empty() {
+    return malformed("expected a value after the name", span);
+  }
+
+  let init = match directive {
+    ".data"   => match parse_literal(operands_text) {
+      Ok(size) if (1..=MAX_LITERAL).contains(&size) => DataInit::Reserved(size as u16),
+      _ => return malformed("`.data` takes a size from 1 to 32767", operands_span),
+    },
+    ".word"   => {
+      let mut values = Vec::new();
+      let commas = operands.iter().enumerate().filter(|(_, token)| token.is_punct(",")).map(|(i, _)| i);
+      let mut start = 0;
+
+      for end in commas.chain(Some(operands.len())) {
+        let value = &operands[start..end];
+
+        // an empty value sits after the comma before it
+        let at = if start == 0 { operands_range.start } else { operands[start - 1].span.end };
+        let range = token_range(value, at);
+        let value_span = sub_span(span, s, range.clone());
+
+        if value.is_empty() {
+          return malformed("empty value", value_span);
+        }
+
+        let expr = Expr::parse(&s[range]).map_err(|error| expr_error(error, value_span))?;
+        values.push((expr, value_span.column - span.column, value_span.len));
+        start = end + 1;
       }
+
+      DataInit::Values(values)
+    },
+    _         => {
+      let chars = parse_string(operands_text).map_err(|why| {
+        AssembleError::new(ErrorKind::BadLiteral(String::from(operands_text), why), operands_span)
+      })?;
+
+      let offset = operands_span.column - span.column;
+      let values = chars
+        .into_iter()
+        .chain(Some(0))
+        .map(|c| (Expr::Num(c), offset, operands_span.len))
+        .collect();
+
+      DataInit::Values(values)
+    },
+  };
+
+  Ok(Instruction::Data { name: String::from(name), init })
+}
+
+// the prologue code that puts `value` into RAM[address]
+fn store_word(address: u16, value: u16) -> Vec<u16> {
+  let store = |comp| vec![address, encode_c(Dest::M, comp, Jump::Null)];
+
+  match value {
+    0      => store(Comp::Zero),
+    1      => store(Comp::One),
+    0xFFFF => store(Comp::NegOne),
+    _      => {
+      let mut words = load(Dest::D, value);
+      words.extend(store(Comp::D));
+      words
     },
-    None => "",
   }
 }
 
-fn parse_jmp(s: &str) -> &str {
-  match s.find(";") {
-    Some(j_index) => {
-      match s.get(j_index+1..) {
-        Some(j) => j,
-        None    => ""
-      }
+// code that sets `dest` to any 16-bit value
+fn load(dest: Dest, value: u16) -> Vec<u16> {
+  let set = |comp| encode_c(dest, comp, Jump::Null);
+
+  match value {
+    0 if dest != Dest::A      => vec![set(Comp::Zero)],
+    1 if dest != Dest::A      => vec![set(Comp::One)],
+    0xFFFF if dest != Dest::A => vec![set(Comp::NegOne)],
+    0..=0x7FFF if dest == Dest::A => vec![value],
+    0..=0x7FFF                => vec![value, set(Comp::A)],
+    // A-instructions only load 15 bits, so the rest are loaded inverted or negated
+    0x8000                    => vec![0x7FFF, set(Comp::NotA)],
+    _                         => vec![value.wrapping_neg(), set(Comp::NegA)],
+  }
+}
+
+// the value of `LOAD`, where constants may use all 16 bits and anything
+// with symbols is an address
+fn load_value(s: &str, span: Span) -> Result<AInstruction, AssembleError> {
+  match Expr::parse(s) {
+    Ok(expr) if expr.symbols().is_empty() => match expr.eval(&|_| None) {
+      Ok(value) if (-0x8000..=0xFFFF).contains(&value) => Ok(AInstruction::Num(value as u16)),
+      Ok(value)  => Err(AssembleError::new(ErrorKind::WordOutOfRange(expr.to_string(), value), span)),
+      Err(error) => Err(expr_error(error, span)),
     },
-    None => ""
-  }
-}
-
-fn jmp_to_binary(jmp: &str) -> &str {
-  match jmp {
-    ""    => "000",
-    "JGT" => "001", 
-    "JEQ" => "010", 
-    "JGE" => "011", 
-    "JLT" => "100", 
-    "JNE" => "101", 
-    "JLE" => "110", 
-    _     =>  "111", // jmp == "JMP"
-  }
-}
-
-fn comp_to_binary(comp: &str) -> &str {
-  match comp {
-    "0"   => "0101010",
-    "1"   => "0111111",
-    "-1"  => "0111010",
-    "D"   => "0001100",
-    "A"   => "0110000",
-    "M"   => "1110000",
-    "!D"  => "0001101",
-    "!A"  => "0110001",
-    "!M"  => "1110001",
-    "-D"  => "0001111",
-    "-A"  => "0110011",
-    "-M"  => "1110011",
-    "D+1" => "0011111",
-    "A+1" => "0110111",
-    "M+1" => "1110111",
-    "D-1" => "0001110",
-    "A-1" => "0110010",
-    "M-1" => "1110010",
-    "D+A" => "0000010",
-    "D+M" => "1000010",
-    "D-A" => "0010011",
-    "D-M" => "1010011",
-    "A-D" => "0000111",
-    "M-D" => "1000111",
-    "D&A" => "0000000",
-    "D&M" => "1000000",
-    "D|A" => "0010101",
-    _     => "1010101", // comp == "D|M"
-  }
-}
-
-fn dest_to_binary(dest: &str) -> &str {
-  match dest {
-    ""          => "000",
-    "M"         => "001",
-    "D"         => "010",
-    "DM" | "MD" => "011",
-    "A"         => "100",
-    "AM" | "MA" => "101",
-    "AD" | "DA" => "110",
-    _    => "111",  // dest = "ADM"
+    _ => AInstruction::get(s, span),
+  }
+}
+
+// `dest=comp;jump`, split at the `=` and `;` tokens. a `;` before the `=`
+// leaves the comp running to the end
+fn parse_c_instruction(s: &str, tokens: &[Token], span: Span) -> Result<Instruction, AssembleError> {
+  let equals = tokens.iter().position(|token| token.is_punct("="));
+  let semicolon = tokens.iter().position(|token| token.is_punct(";"));
+
+  let comp_start = equals.map_or(0, |i| i + 1);
+  let comp_end = semicolon.filter(|&i| i >= comp_start).unwrap_or(tokens.len());
+  let jump_start = semicolon.map_or(tokens.len(), |i| i + 1);
+
+  let (dest_field, dest_range) = field(&tokens[..equals.unwrap_or(0)], 0);
+  let (comp_field, comp_range) = field(&tokens[comp_start..comp_end], equals.map_or(0, |i| tokens[i].span.end));
+  let (jump_field, jump_range) = field(&tokens[jump_start..], semicolon.map_or(s.len(), |i| tokens[i].span.end));
+
+  let dest = Dest::parse(&dest_field).ok_or_else(|| {
+    let kind = ErrorKind::UnknownDest(String::from(&s[dest_range.clone()]));
+    AssembleError::new(kind, sub_span(span, s, dest_range))
+  })?;
+  let comp = Comp::parse(&comp_field).ok_or_else(|| {
+    let kind = ErrorKind::UnknownComp(String::from(&s[comp_range.clone()]));
+    AssembleError::new(kind, sub_span(span, s, comp_range))
+  })?;
+  let jump = Jump::parse(&jump_field).ok_or_else(|| {
+    let kind = ErrorKind::UnknownJump(String::from(&s[jump_range.clone()]));
+    AssembleError::new(kind, sub_span(span, s, jump_range))
+  })?;
+
+  Ok(Instruction::CInstruction { dest, comp, jump })
+}
+
+// a field's tokens run together, so `D = D + 1` reads as `D=D+1`, and the
+// bytes they cover; an empty field sits at `at`
+fn field(tokens: &[Token], at: usize) -> (String, Range<usize>) {
+  (tokens.iter().map(|token| token.text).collect(), token_range(tokens, at))
+}
+
+// the bytes from the first token to the last, or an empty range at `at`
+fn token_range(tokens: &[Token], at: usize) -> Range<usize> {
+  match (tokens.first(), tokens.last()) {
+    (Some(first), Some(last)) => first.span.start..last.span.end,
+    _                         => at..at,
   }
 }
 
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-  let input = fs::read_to_string(config.input_filename)?;
+  if let Some(message) = &config.message {
+    println!("{}", message);
+    return Ok(());
+  }
+
+  if let [job] = config.jobs.as_slice() {
+    return run_job(&config, job);
+  }
+
+  // one bad file should not stop the others from being assembled
+  let mut failed = 0;
+  for job in &config.jobs {
+    if let Err(e) = run_job(&config, job) {
+      eprintln!("{}: {}", job.input_filenames[0], e);
+      failed += 1;
+    }
+  }
+
+  if failed > 0 {
+    return Err(format!("{} of {} inputs failed", failed, config.jobs.len()).into());
+  }
+
+  Ok(())
+}
+
+fn run_job(config: &Config, job: &Job) -> Result<(), Box<dyn Error>> {
+  let input_filename = &job.input_filenames[0];
+  let file_name = if input_filename == STANDARD_STREAM { "<stdin>" } else { input_filename };
+  let options = Options {
+    file_name: String::from(file_name),
+    include_paths: config.include_paths.clone(),
+    defines: config.defines.clone(),
+    raw_alu: config.raw_alu,
+    optimize: config.optimize,
+    strict: config.strict,
+    variable_base: config.variable_base,
+    variable_limit: config.variable_limit,
+    error_limit: config.error_limit,
+  };
+
+  let output = match job.mode {
+    Mode::Assemble if config.object => {
+      let input = String::from_utf8(read_input(input_filename)?)?;
+      let object = assemble_object(&input, &options)?;
+      report_warnings(config, &object.warnings)?;
+
+      object.to_text().into_bytes()
+    },
+    Mode::Assemble               => {
+      let input = String::from_utf8(read_input(input_filename)?)?;
+      let program = assemble(&input, &options)?;
+      report_warnings(config, &program.warnings)?;
+
+      if config.optimize && !config.quiet {
+        eprintln!("optimizer saved {} of {} words", program.words_saved, program.words.len() + program.words_saved);
+      }
+
+      if let Some(listing_filename) = &job.listing_filename {
+        fs::write(listing_filename, listing(&program))?;
+      }
+
+      write_symbols(job, &program)?;
+      write_machine_code(&program.words, config.format)
+    },
+    Mode::Link                   => {
+      let mut objects = Sources::default();
+      for filename in &job.input_filenames {
+        objects.add(filename, fs::read_to_string(filename)?, None);
+      }
+
+      let program = link(objects, &options)?;
+      report_warnings(config, &program.warnings)?;
+
+      write_symbols(job, &program)?;
+      write_machine_code(&program.words, config.format)
+    },
+    Mode::Disassemble(format)    => {
+      let input = read_input(input_filename)?;
+      let symbols = match &config.symbol_filename {
+        Some(symbol_filename) => read_symbols(symbol_filename, config.error_limit)?,
+        None                  => Symbols::default(),
+      };
+
+      let mut diagnostics = Diagnostics::new(config.error_limit);
+      let (words, locations) = read_machine_code(&input, format, &mut diagnostics);
+      let output = disassemble(&words, &locations, &symbols, &mut diagnostics);
+
+      let diagnostics = if format.is_text() {
+        diagnostics.with_source(input_filename, &String::from_utf8_lossy(&input))
+      } else {
+        diagnostics.with_file(input_filename)
+      };
+      if diagnostics.has_errors() {
+        return Err(Box::new(diagnostics));
+      }
+
+      report_warnings(config, &diagnostics)?;
+      output.into_bytes()
+    },
+    Mode::Format                 => {
+      let input = String::from_utf8(read_input(input_filename)?)?;
+      let formatted = format_source(&input);
+
+      // --check is for scripts and review, so it only says whether the file would change
+      if config.check {
+        if input == formatted {
+          return Ok(());
+        }
+
+        let line = input.lines().zip(formatted.lines()).take_while(|(old, new)| old == new).count() + 1;
+        return Err(format!("not formatted, from line {}", line).into());
+      }
+
+      formatted.into_bytes()
+    },
+  };
+
+  if job.output_filename == STANDARD_STREAM {
+    io::stdout().write_all(&output)?;
+  } else {
+    fs::write(&job.output_filename, output)?;
+  }
 
-  let assembly = Assembly::new(input);
-  let assembler = Assembler::new();
+  Ok(())
+}
+
+// print warnings unless --quiet, or fail with them under --werror
+fn report_warnings(config: &Config, warnings: &Diagnostics) -> Result<(), Box<dyn Error>> {
+  if warnings.is_empty() {
+    return Ok(());
+  }
+
+  if config.werror {
+    return Err(Box::new(warnings.clone().into_errors()));
+  }
+
+  if !config.quiet {
+    eprintln!("{}", warnings);
+  }
 
-  fs::write(config.output_filename, assembler.translate(&assembly.instructions))?;
   Ok(())
-}
\ No newline at end of file
+}
+
+fn read_input(filename: &str) -> io::Result<Vec<u8>> {
+  if filename == STANDARD_STREAM {
+    let mut input = Vec::new();
+    io::stdin().read_to_end(&mut input)?;
+    Ok(input)
+  } else {
+    fs::read(filename)
+  }
+}
+
+fn write_symbols(job: &Job, program: &Program) -> Result<(), Box<dyn Error>> {
+  match &job.symbols_filename {
+    Some((filename, SymbolFormat::Sym))  => fs::write(filename, program.symbols.to_sym(&program.sources))?,
+    Some((filename, SymbolFormat::Json)) => fs::write(filename, program.symbols.to_json(&program.sources))?,
+    None                                 => (),
+  }
+
+  Ok(())
+}
+
+fn read_symbols(filename: &str, error_limit: usize) -> Result<Symbols, Box<dyn Error>> {
+  let input = fs::read_to_string(filename)?;
+  let mut diagnostics = Diagnostics::new(error_limit);
+
+  let symbols = Symbols::parse(&input, &mut diagnostics);
+
+  let diagnostics = diagnostics.with_source(filename, &input);
+  if diagnostics.has_errors() {
+    return Err(Box::new(diagnostics));
+  }
+
+  Ok(symbols)
+}
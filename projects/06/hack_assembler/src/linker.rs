@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+use crate::error::{AssembleError, Diagnostics, ErrorKind, Span};
+use crate::object::{Object, ObjectWord};
+use crate::source::Sources;
+use crate::symbol_table::SymbolKind;
+use crate::{predefined_symbols, Options, Program, Ram, MAX_LITERAL, ROM_SIZE};
+
+/// Combine object files into one program.
+///
+/// The objects are placed in ROM one after the other in the order given, so
+/// the first one starts at address 0. Exported labels resolve every `extern`
+/// of the same name, and variables with the same name in different objects
+/// share one RAM address, allocated from `options.variable_base`.
+pub fn link(sources: Sources, options: &Options) -> Result<Program, Diagnostics> {
+  let mut diagnostics = Diagnostics::new(options.error_limit);
+
+  let objects: Vec<Object> = sources
+    .iter()
+    .zip(0..)
+    .map(|(source, file)| Object::parse(&source.text, file, &mut diagnostics))
+    .collect();
+
+  // each object starts where the one before it ends
+  let mut bases = Vec::with_capacity(objects.len());
+  let mut base = 0;
+  for object in &objects {
+    bases.push(base);
+    base += object.words.len() as i64;
+  }
+
+  // exports become the labels of the linked program
+  let mut symbol_table = predefined_symbols();
+  let mut exporters: HashMap<&str, Span> = HashMap::new();
+  for (object, base) in objects.iter().zip(&bases) {
+    for (name, address, span) in &object.exports {
+      if let Some(first) = exporters.get(name.as_str()) {
+        let file = sources.get(first.file).map_or("<input>", |source| source.name.as_str());
+        let diagnostic = AssembleError::new(ErrorKind::DuplicateExport(name.clone()), *span)
+          .with_note(format!("first exported by {}:{}", file, first.line));
+        diagnostics.push(diagnostic);
+        continue;
+      }
+
+      if symbol_table.contains(name) {
+        diagnostics.push(AssembleError::new(ErrorKind::DuplicateSymbol(name.clone(), None), *span));
+        continue;
+      }
+
+      symbol_table.insert(name, SymbolKind::Label, (base + i64::from(*address)) as u16, *span);
+      exporters.insert(name, *span);
+    }
+  }
+
+  // variables get RAM in the order they are first used
+  let mut ram = Ram::new(options.variable_base, options.variable_limit);
+  let mut words = Vec::with_capacity(base as usize);
+  let mut locations = Vec::with_capacity(base as usize);
+  for (object, base) in objects.iter().zip(&bases) {
+    for (word, span) in object.words.iter().zip(&object.locations) {
+      let value = match word {
+        ObjectWord::Absolute(word)         => i64::from(*word),
+        ObjectWord::Label(address)         => base + address,
+        ObjectWord::Extern(name, addend)   => {
+          match symbol_table.get(name).map(|symbol| (symbol.kind, symbol.address)) {
+            Some((SymbolKind::Label, address)) => i64::from(address) + addend,
+            _ => {
+              diagnostics.push(AssembleError::new(ErrorKind::UnresolvedExtern(name.clone()), *span));
+              0
+            },
+          }
+        },
+        ObjectWord::Variable(name, addend) => {
+          match symbol_table.get(name).map(|symbol| (symbol.kind, symbol.address)) {
+            Some((SymbolKind::Label, _)) => {
+              diagnostics.push(AssembleError::new(ErrorKind::VariableIsExported(name.clone()), *span));
+              0
+            },
+            Some((_, address)) => i64::from(address) + addend,
+            None               => {
+              let address = ram.allocate(name, 1, *span, &mut diagnostics);
+              symbol_table.insert(name, SymbolKind::Variable, address, *span);
+              i64::from(address) + addend
+            },
+          }
+        },
+      };
+
+      // relocated words are always A-instructions
+      let absolute = matches!(word, ObjectWord::Absolute(_));
+      if !absolute && !(0..=MAX_LITERAL).contains(&value) {
+        let kind = ErrorKind::ExpressionOutOfRange(word.to_string(), value);
+        diagnostics.push(AssembleError::new(kind, *span));
+      }
+
+      if let ObjectWord::Extern(name, _) | ObjectWord::Variable(name, _) = word {
+        symbol_table.mark_use(name, *span);
+      }
+
+      words.push(value as u16);
+      locations.push(*span);
+    }
+  }
+
+  if let Some(span) = locations.get(ROM_SIZE) {
+    diagnostics.push(AssembleError::new(ErrorKind::RomOverflow(words.len()), *span));
+  }
+
+  let diagnostics = diagnostics.with_sources(&sources);
+  if diagnostics.has_errors() {
+    return Err(diagnostics);
+  }
+
+  Ok(Program {
+    words,
+    locations,
+    symbols: symbol_table,
+    warnings: diagnostics,
+    sources,
+    words_saved: 0,
+  })
+}
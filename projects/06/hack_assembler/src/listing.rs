@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use crate::disassembler::c_instruction;
+use crate::lexer::lines;
+use crate::symbol_table::SymbolKind;
+use crate::Program;
+
+// every source line next to the ROM address and encoding of the words it produced;
+// label definitions show the ROM address they resolve to, and included files are
+// listed in place of the `.include` that pulled them in, under the name of the
+// file each run of lines comes from. lines that produce several words are
+// followed by the instruction each word encodes
+pub fn listing(program: &Program) -> String {
+  let mut words_by_line: HashMap<(u32, usize), Vec<usize>> = HashMap::new();
+  for (address, span) in program.locations.iter().enumerate() {
+    words_by_line.entry((span.file, span.line)).or_default().push(address);
+  }
+
+  let labels_by_line: HashMap<(u32, usize), u16> = program.symbols
+    .iter()
+    .filter(|symbol| symbol.kind == SymbolKind::Label)
+    .filter_map(|symbol| symbol.span.map(|span| ((span.file, span.line), symbol.address)))
+    .collect();
+
+  let includes_by_line: HashMap<(u32, usize), u32> = program.sources
+    .iter()
+    .zip(0..)
+    .filter_map(|(source, file)| source.included_from.map(|span| ((span.file, span.line), file)))
+    .collect();
+
+  let listing = Listing {
+    program,
+    words_by_line,
+    labels_by_line,
+    includes_by_line,
+  };
+
+  let mut output = format!("{:>5}  {:16}  {:4}  {:>5}  {}\n", "ROM", "binary", "hex", "line", "source");
+  listing.file(0, &mut output);
+  output
+}
+
+struct Listing<'a> {
+  program: &'a Program,
+  words_by_line: HashMap<(u32, usize), Vec<usize>>,
+  labels_by_line: HashMap<(u32, usize), u16>,
+  includes_by_line: HashMap<(u32, usize), u32>,
+}
+
+impl<'a> Listing<'a> {
+  fn file(&self, file: u32, output: &mut String) {
+    let source = match self.program.sources.get(file) {
+      Some(source) => source,
+      None         => return,
+    };
+
+    let mut lines = lines(&source.text);
+    while lines.len() > 1 && lines.last() == Some(&"") {
+      lines.pop();
+    }
+
+    // with includes, each run of lines is headed by the file it comes from
+    let mut named = self.program.sources.iter().count() == 1;
+    for (index, &text) in lines.iter().enumerate() {
+      let number = index + 1;
+      let key = (file, number);
+
+      if !named {
+        output.push_str(&format!("{:5}  {:16}  {:4}  {:5}  ==> {} <==\n", "", "", "", "", source.name));
+        named = true;
+      }
+
+      match (self.words_by_line.get(&key), self.labels_by_line.get(&key)) {
+        (Some(addresses), _) if addresses.len() == 1 => {
+          let word = self.program.words[addresses[0]];
+          output.push_str(&format!("{:05}  {:016b}  {:04X}  {:5}  {}\n", addresses[0], word, word, number, text));
+        },
+        (Some(addresses), _) => {
+          output.push_str(&format!("{:05}  {:16}  {:4}  {:5}  {}\n", addresses[0], "", "", number, text));
+
+          let indent: String = text.chars().take_while(|c| c.is_whitespace()).collect();
+          for &address in addresses {
+            let word = self.program.words[address];
+            let instruction = if word & 0x8000 == 0 { format!("@{}", word) } else { c_instruction(word) };
+            output.push_str(&format!("{:05}  {:016b}  {:04X}  {:5}  {}    {}\n", address, word, word, number, indent, instruction));
+          }
+        },
+        (None, Some(address)) => {
+          output.push_str(&format!("{:05}  {:16}  {:4}  {:5}  {}\n", address, "", "", number, text));
+        },
+        (None, None) => {
+          output.push_str(&format!("{:5}  {:16}  {:4}  {:5}  {}\n", "", "", "", number, text));
+        },
+      }
+
+      if let Some(&included) = self.includes_by_line.get(&key) {
+        self.file(included, output);
+        named = false;
+      }
+    }
+  }
+}
@@ -0,0 +1,94 @@
+// numeric literals: decimal, `0x` hex, `0b` binary and `'c'` ASCII characters.
+// a leading `-` is allowed so out of range values get a proper message.
+// `"..."` strings only appear in directives.
+
+pub fn is_literal_start(c: char) -> bool {
+  c.is_ascii_digit() || c == '-' || c == '\''
+}
+
+// the literal's value, or why it is malformed
+pub fn parse_literal(s: &str) -> Result<i64, String> {
+  if let Some(rest) = s.strip_prefix('-') {
+    return parse_literal(rest).map(|value| -value);
+  }
+
+  if s.starts_with('\'') {
+    return parse_char(s);
+  }
+
+  let (digits, radix, name) = if let Some(digits) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+    (digits, 16, "hex")
+  } else if let Some(digits) = s.strip_prefix("0b").or_else(|| s.strip_prefix("0B")) {
+    (digits, 2, "binary")
+  } else {
+    (s, 10, "decimal")
+  };
+
+  if digits.is_empty() {
+    return Err(format!("{} literal has no digits", name));
+  }
+
+  if let Some(c) = digits.chars().find(|c| !c.is_digit(radix)) {
+    return Err(format!("invalid digit `{}` in {} literal", c, name));
+  }
+
+  // anything that overflows is far outside the 15-bit range anyway
+  Ok(i64::from_str_radix(digits, radix).unwrap_or(i64::MAX))
+}
+
+fn parse_char(s: &str) -> Result<i64, String> {
+  let inner = match s.get(1..).and_then(|rest| rest.strip_suffix('\'')) {
+    Some(inner) if s.len() >= 2 => inner,
+    _                           => return Err(String::from("unterminated character literal")),
+  };
+
+  let c = match inner {
+    "\\n"  => '\n',
+    "\\t"  => '\t',
+    "\\\\" => '\\',
+    "\\'"  => '\'',
+    _ if inner.chars().count() == 1 => inner.chars().next().unwrap(),
+    _      => return Err(String::from("character literal must hold exactly one character")),
+  };
+
+  ascii(c)
+}
+
+// the characters of a string, with the escapes of character literals plus `\"`
+pub fn parse_string(s: &str) -> Result<Vec<i64>, String> {
+  let inner = match s.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+    Some(inner) => inner,
+    None        => return Err(String::from("expected a string in double quotes")),
+  };
+
+  let mut values = Vec::new();
+  let mut chars = inner.chars();
+
+  while let Some(c) = chars.next() {
+    let c = match c {
+      '\\' => match chars.next() {
+        Some('n')  => '\n',
+        Some('t')  => '\t',
+        Some('\\') => '\\',
+        Some('\'') => '\'',
+        Some('"')  => '"',
+        Some(c)    => return Err(format!("unknown escape `\\{}`", c)),
+        None       => return Err(String::from("unterminated string")),
+      },
+      '"'  => return Err(String::from("`\"` inside a string must be escaped")),
+      c    => c,
+    };
+
+    values.push(ascii(c)?);
+  }
+
+  Ok(values)
+}
+
+fn ascii(c: char) -> Result<i64, String> {
+  if !c.is_ascii() {
+    return Err(format!("`{}` is not an ASCII character", c));
+  }
+
+  Ok(c as i64)
+}
@@ -0,0 +1,639 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::error::{Diagnostics, Severity, Span};
+use crate::json::Json;
+use crate::lexer::{lines, Token, TokenKind};
+use crate::symbol_table::{Symbol, SymbolKind, SymbolTable};
+use crate::syntax::{LineKind, SyntaxTree};
+use crate::{analyze, is_symbol, resolve_local, Instruction, Options, Program};
+
+// JSON-RPC error codes, and the one LSP adds for a request that cannot be done
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+// LSP's numbers for diagnostic severities, text sync and completion kinds
+const SEVERITY_ERROR: usize = 1;
+const SEVERITY_WARNING: usize = 2;
+const SYNC_FULL: usize = 1;
+const COMPLETION_VARIABLE: usize = 6;
+const COMPLETION_REFERENCE: usize = 18;
+const COMPLETION_CONSTANT: usize = 21;
+
+type RequestResult = Result<Json, (i64, String)>;
+
+/// Serve the Language Server Protocol over `input` and `output`, normally
+/// stdin and stdout, until the client sends `exit`.
+///
+/// Each open `.asm` document is assembled on every change to publish its
+/// diagnostics. The server answers go-to-definition, find-references, hover,
+/// completion and rename requests for the labels, variables and data blocks
+/// of the document, using the assembler's own symbol resolution.
+pub fn run_language_server(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+  let mut server = Server {
+    documents: HashMap::new(),
+    shutdown: false,
+  };
+
+  while let Some(body) = read_message(&mut input)? {
+    let message = match Json::parse(&body) {
+      Ok(message) => message,
+      Err(e)      => {
+        send(&mut output, &response(&Json::Null, Err((PARSE_ERROR, e))))?;
+        continue;
+      },
+    };
+
+    if message.get("method").as_str() == Some("exit") {
+      return if server.shutdown {
+        Ok(())
+      } else {
+        Err(io::Error::other("exit before shutdown"))
+      };
+    }
+
+    for reply in server.handle(&message) {
+      send(&mut output, &reply)?;
+    }
+  }
+
+  Ok(())
+}
+
+struct Server {
+  documents: HashMap<String, Document>,
+  shutdown: bool,
+}
+
+// an open file as last assembled
+struct Document {
+  text: String,
+  program: Program,
+  diagnostics: Diagnostics,
+  occurrences: Vec<Occurrence>,
+}
+
+// a symbol written in the document: its name as resolved by the assembler,
+// and its 0-based line, char column and length in chars
+struct Occurrence {
+  name: String,
+  line: usize,
+  column: usize,
+  len: usize,
+  // the labels of the macro body it is written in, which a rename must not take
+  macro_labels: Vec<String>,
+}
+
+impl Server {
+  // the responses and notifications to send back for one message
+  fn handle(&mut self, message: &Json) -> Vec<Json> {
+    let id = message.get("id");
+    let params = message.get("params");
+    let method = message.get("method").as_str().unwrap_or("");
+    let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+
+    match method {
+      "textDocument/didOpen"   => {
+        let text = params.get("textDocument").get("text").as_str().unwrap_or("");
+        return vec![self.update(uri, text)];
+      },
+      // only whole documents are synced, so the last change holds all the text
+      "textDocument/didChange" => {
+        let changes = params.get("contentChanges").as_array().unwrap_or(&[]);
+        return match changes.last().and_then(|change| change.get("text").as_str()) {
+          Some(text) => vec![self.update(uri, text)],
+          None       => Vec::new(),
+        };
+      },
+      "textDocument/didClose"  => {
+        self.documents.remove(uri);
+        return vec![publish_diagnostics(uri, Vec::new())];
+      },
+      // other notifications, such as `initialized`, need nothing from us
+      _ if id.is_null()        => return Vec::new(),
+      _                        => (),
+    }
+
+    let result = match method {
+      "initialize"              => Ok(capabilities()),
+      "shutdown"                => {
+        self.shutdown = true;
+        Ok(Json::Null)
+      },
+      _ if self.shutdown        => Err((INVALID_REQUEST, String::from("the server is shutting down"))),
+      "textDocument/definition" => self.locate(params).map(|(document, line, column)| document.definition(uri, line, column)),
+      "textDocument/references" => self.locate(params).map(|(document, line, column)| {
+        let declaration = params.get("context").get("includeDeclaration").as_bool().unwrap_or(true);
+        document.references(uri, line, column, declaration)
+      }),
+      "textDocument/hover"      => self.locate(params).map(|(document, line, column)| document.hover(line, column)),
+      "textDocument/completion" => self.locate(params).map(|(document, _, _)| document.completion()),
+      "textDocument/rename"     => self.locate(params).and_then(|(document, line, column)| {
+        let new_name = params.get("newName").as_str().unwrap_or("");
+        document.rename(uri, line, column, new_name)
+      }),
+      _                         => Err((METHOD_NOT_FOUND, format!("unknown method `{}`", method))),
+    };
+
+    vec![response(id, result)]
+  }
+
+  fn update(&mut self, uri: &str, text: &str) -> Json {
+    let document = Document::new(uri, text);
+    let diagnostics = document.diagnostics_json();
+
+    self.documents.insert(String::from(uri), document);
+    publish_diagnostics(uri, diagnostics)
+  }
+
+  // the document a request is about, and the line and char column it asks about
+  fn locate(&self, params: &Json) -> Result<(&Document, usize, usize), (i64, String)> {
+    let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+    let document = match self.documents.get(uri) {
+      Some(document) => document,
+      None           => return Err((INVALID_PARAMS, format!("`{}` is not open", uri))),
+    };
+
+    let position = params.get("position");
+    let line = position.get("line").as_u64().unwrap_or(0) as usize;
+    let character = position.get("character").as_u64().unwrap_or(0) as usize;
+
+    Ok((document, line, char_column(document.line(line), character)))
+  }
+}
+
+impl Document {
+  fn new(uri: &str, text: &str) -> Document {
+    // the file name lets `.include` find files next to the document
+    let options = Options {
+      file_name: uri_to_path(uri),
+      ..Options::default()
+    };
+
+    let (program, _, diagnostics) = analyze(text, &options, false);
+    let occurrences = occurrences(text, &program.symbols);
+
+    Document {
+      text: String::from(text),
+      program,
+      diagnostics,
+      occurrences,
+    }
+  }
+
+  fn line(&self, line: usize) -> &str {
+    lines(&self.text).get(line).copied().unwrap_or("")
+  }
+
+  // the symbol the cursor is on or right after
+  fn occurrence_at(&self, line: usize, column: usize) -> Option<&Occurrence> {
+    self.occurrences
+      .iter()
+      .find(|occurrence| occurrence.line == line && (occurrence.column..=occurrence.column + occurrence.len).contains(&column))
+  }
+
+  fn symbol_at(&self, line: usize, column: usize) -> Option<&Symbol> {
+    self.occurrence_at(line, column).and_then(|occurrence| self.program.symbols.get(&occurrence.name))
+  }
+
+  fn diagnostics_json(&self) -> Vec<Json> {
+    let sources = &self.program.sources;
+
+    self.diagnostics
+      .iter()
+      .map(|diagnostic| {
+        // problems in included files are shown on the `.include` that brought them in
+        let (span, file) = match sources.include_chain(diagnostic.span.file).last() {
+          Some(include) => (*include, sources.get(diagnostic.span.file).map(|source| source.name.as_str())),
+          None          => (diagnostic.span, None),
+        };
+
+        let mut message = match file {
+          Some(file) => format!("{}: {}", file, diagnostic.kind),
+          None       => diagnostic.kind.to_string(),
+        };
+        for note in diagnostic.notes() {
+          message.push_str(&format!("\n{}", note));
+        }
+
+        let severity = match diagnostic.severity {
+          Severity::Error   => SEVERITY_ERROR,
+          Severity::Warning => SEVERITY_WARNING,
+        };
+
+        Json::object(vec![
+          ("range", self.span_range(span)),
+          ("severity", severity.into()),
+          ("source", "hack_assembler".into()),
+          ("message", message.into()),
+        ])
+      })
+      .collect()
+  }
+
+  fn span_range(&self, span: Span) -> Json {
+    range(self.line(span.line - 1), span.line - 1, span.column - 1, span.len)
+  }
+
+  fn definition(&self, uri: &str, line: usize, column: usize) -> Json {
+    let span = match self.symbol_at(line, column).and_then(|symbol| symbol.span) {
+      Some(span) => span,
+      None       => return Json::Null,
+    };
+
+    // a symbol may be defined in an included file
+    match self.program.sources.get(span.file) {
+      Some(source) if span.file != 0 => {
+        let line_text = lines(&source.text).get(span.line - 1).copied().unwrap_or("");
+        location(&path_to_uri(&source.name), range(line_text, span.line - 1, span.column - 1, span.len))
+      },
+      _                              => location(uri, self.span_range(span)),
+    }
+  }
+
+  fn references(&self, uri: &str, line: usize, column: usize, declaration: bool) -> Json {
+    let symbol = match self.symbol_at(line, column) {
+      Some(symbol) => symbol,
+      None         => return Json::Null,
+    };
+
+    let defined_on = symbol.span.filter(|span| span.file == 0).map(|span| span.line - 1);
+    let locations = self.occurrences
+      .iter()
+      .filter(|occurrence| occurrence.name == symbol.name)
+      .filter(|occurrence| declaration || Some(occurrence.line) != defined_on)
+      .map(|occurrence| location(uri, self.occurrence_range(occurrence)))
+      .collect();
+
+    Json::Array(locations)
+  }
+
+  fn occurrence_range(&self, occurrence: &Occurrence) -> Json {
+    range(self.line(occurrence.line), occurrence.line, occurrence.column, occurrence.len)
+  }
+
+  // what the symbol under the cursor stands for and the words the line assembles to
+  fn hover(&self, line: usize, column: usize) -> Json {
+    let mut sections = Vec::new();
+
+    if let Some(symbol) = self.symbol_at(line, column) {
+      let value = match symbol.kind {
+        SymbolKind::Label   => format!("ROM address {}", symbol.address),
+        SymbolKind::Defined => format!("value {}", symbol.address),
+        _                   => format!("RAM address {}", symbol.address),
+      };
+      sections.push(format!("{} `{}`: {}", symbol.kind, symbol.name, value));
+    }
+
+    let words: Vec<String> = self.program.locations
+      .iter()
+      .zip(&self.program.words)
+      .enumerate()
+      .filter(|(_, (span, _))| span.file == 0 && span.line == line + 1)
+      .map(|(address, (_, word))| format!("{:<5}  {:016b}", address, word))
+      .collect();
+    if !words.is_empty() {
+      sections.push(format!("```\n{}\n```", words.join("\n")));
+    }
+
+    if sections.is_empty() {
+      return Json::Null;
+    }
+
+    let contents = Json::object(vec![("kind", "markdown".into()), ("value", sections.join("\n\n").into())]);
+    Json::object(vec![("contents", contents)])
+  }
+
+  // every symbol, leaving out the copies of labels made by macro expansions
+  fn completion(&self) -> Json {
+    let items = self.program.symbols
+      .iter()
+      .filter(|symbol| !symbol.name.contains('$'))
+      .map(|symbol| {
+        let (kind, detail) = match symbol.kind {
+          SymbolKind::Label      => (COMPLETION_REFERENCE, format!("label, ROM {}", symbol.address)),
+          SymbolKind::Defined    => (COMPLETION_CONSTANT, format!("defined as {}", symbol.address)),
+          SymbolKind::Predefined => (COMPLETION_CONSTANT, format!("predefined, RAM {}", symbol.address)),
+          _                      => (COMPLETION_VARIABLE, format!("{}, RAM {}", symbol.kind, symbol.address)),
+        };
+
+        Json::object(vec![
+          ("label", symbol.name.as_str().into()),
+          ("kind", kind.into()),
+          ("detail", detail.into()),
+        ])
+      })
+      .collect();
+
+    Json::Array(items)
+  }
+
+  // renaming a global label also renames its local labels where they are
+  // written out in full, such as `@LOOP.end` outside the scope of `LOOP`
+  fn rename(&self, uri: &str, line: usize, column: usize, new_name: &str) -> RequestResult {
+    let (occurrence, symbol) = match (self.occurrence_at(line, column), self.symbol_at(line, column)) {
+      (Some(occurrence), Some(symbol)) if symbol.kind == SymbolKind::Label && symbol.span.is_some_and(|span| span.file == 0) => {
+        (occurrence, symbol)
+      },
+      _ => return Err((REQUEST_FAILED, String::from("only labels defined in this file can be renamed"))),
+    };
+
+    // a local label is written without its scope, and must stay local
+    let written = &self.line(occurrence.line).chars().skip(occurrence.column).take(occurrence.len).collect::<String>();
+    let scope = &symbol.name[..symbol.name.len() - written.len()];
+    if !is_symbol(new_name) || new_name.starts_with('.') != written.starts_with('.') {
+      return Err((REQUEST_FAILED, format!("`{}` is not a valid name for `{}`", new_name, written)));
+    }
+
+    let renamed = format!("{}{}", scope, new_name);
+    if self.program.symbols.contains(&renamed) {
+      return Err((REQUEST_FAILED, format!("`{}` is already defined", renamed)));
+    }
+
+    // in a macro body the new name would be taken for the macro's own label
+    let captured = self.occurrences
+      .iter()
+      .any(|occurrence| occurrence.name == symbol.name && occurrence.macro_labels.contains(&renamed));
+    if captured {
+      return Err((REQUEST_FAILED, format!("`{}` is a label of a macro that uses `{}`", renamed, symbol.name)));
+    }
+
+    let locals = format!("{}.", symbol.name);
+    let edits = self.occurrences
+      .iter()
+      .filter_map(|occurrence| {
+        let full = occurrence.len == occurrence.name.chars().count();
+        let text = if occurrence.name == symbol.name {
+          if full { renamed.clone() } else { String::from(new_name) }
+        } else if full && occurrence.name.starts_with(&locals) {
+          format!("{}{}", renamed, &occurrence.name[symbol.name.len()..])
+        } else {
+          return None;
+        };
+
+        Some(Json::object(vec![("range", self.occurrence_range(occurrence)), ("newText", text.into())]))
+      })
+      .collect();
+
+    let changes = Json::Object(vec![(String::from(uri), Json::Array(edits))]);
+    Ok(Json::object(vec![("changes", changes)]))
+  }
+}
+
+// where the symbols of the table are written in the source. C-instructions
+// are left out, since their `A`, `D` and `M` are registers even if a label
+// shares the name, and so are a macro's own labels, which are renamed for
+// each expansion
+fn occurrences(text: &str, symbols: &SymbolTable) -> Vec<Occurrence> {
+  let tree = SyntaxTree::parse(text);
+  let bodies = macro_bodies(&tree);
+
+  let mut occurrences = Vec::new();
+  let mut scope = String::new();
+
+  for line in &tree.lines {
+    let code = line.code();
+    let tokens: Vec<&Token> = line.code_tokens().collect();
+    let first = tokens.first().map_or("", |token| token.text);
+    let body = bodies.iter().find(|body| body.lines.contains(&line.number));
+
+    let skip = match line.kind {
+      LineKind::Label if body.is_none()   => {
+        if let Ok(Instruction::LInstruction(label)) = Instruction::get(code, line.span()) {
+          if !label.starts_with('.') {
+            scope = label;
+          }
+        }
+        false
+      },
+      LineKind::Label                     => false,
+      LineKind::Directive                 => !matches!(first, ".if" | ".ifdef"),
+      LineKind::Code                      => {
+        matches!(Instruction::get(code, line.span()), Ok(Instruction::CInstruction { .. }))
+      },
+      LineKind::Blank | LineKind::Comment => true,
+    };
+    if skip {
+      continue;
+    }
+
+    for (i, token) in tokens.iter().enumerate() {
+      // the directive itself, a macro parameter or a number
+      let directive = i == 0 && token.text.starts_with('.');
+      let parameter = i > 0 && tokens[i - 1].is_punct("\\");
+      if token.kind != TokenKind::Word || directive || parameter || token.text.starts_with(|c: char| c.is_ascii_digit()) {
+        continue;
+      }
+
+      // a local name in a macro body belongs to the scope of each call
+      let own_label = body.is_some_and(|body| token.text.starts_with('.') || body.labels.iter().any(|label| label == token.text));
+      if own_label {
+        continue;
+      }
+
+      let name = resolve_local(symbols, &scope, token.text);
+      if symbols.contains(&name) {
+        occurrences.push(Occurrence {
+          name,
+          line: line.number - 1,
+          column: line.column(token.span.start) - 1,
+          len: token.text.chars().count(),
+          macro_labels: body.map_or_else(Vec::new, |body| body.labels.clone()),
+        });
+      }
+    }
+  }
+
+  occurrences
+}
+
+// the lines of a macro definition between `.macro` and `.endm`, and the
+// labels defined there
+struct MacroBody {
+  lines: Range<usize>,
+  labels: Vec<String>,
+}
+
+fn macro_bodies(tree: &SyntaxTree) -> Vec<MacroBody> {
+  let mut bodies = Vec::new();
+  let mut open: Option<MacroBody> = None;
+
+  for line in &tree.lines {
+    let first = line.code_tokens().next().map_or("", |token| token.text);
+    if let Some(body) = &mut open {
+      body.lines.end = line.number;
+    }
+
+    match line.kind {
+      LineKind::Directive if first == ".macro" => {
+        open = Some(MacroBody { lines: line.number + 1..line.number + 1, labels: Vec::new() });
+      },
+      LineKind::Directive if first == ".endm"  => bodies.extend(open.take()),
+      LineKind::Label                          => {
+        if let (Some(body), Ok(Instruction::LInstruction(label))) = (&mut open, Instruction::get(line.code(), line.span())) {
+          body.labels.push(label);
+        }
+      },
+      _                                        => (),
+    }
+  }
+
+  // a macro without `.endm` runs to the end of the file
+  bodies.extend(open.map(|body| MacroBody { lines: body.lines.start..tree.lines.len() + 1, ..body }));
+  bodies
+}
+
+fn capabilities() -> Json {
+  let completion = Json::object(vec![("triggerCharacters", vec![Json::from("@")].into())]);
+  let capabilities = Json::object(vec![
+    ("textDocumentSync", SYNC_FULL.into()),
+    ("definitionProvider", true.into()),
+    ("referencesProvider", true.into()),
+    ("hoverProvider", true.into()),
+    ("completionProvider", completion),
+    ("renameProvider", true.into()),
+  ]);
+  let server_info = Json::object(vec![
+    ("name", "hack_lsp".into()),
+    ("version", env!("CARGO_PKG_VERSION").into()),
+  ]);
+
+  Json::object(vec![("capabilities", capabilities), ("serverInfo", server_info)])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+  let params = Json::object(vec![("uri", uri.into()), ("diagnostics", diagnostics.into())]);
+  notification("textDocument/publishDiagnostics", params)
+}
+
+fn notification(method: &str, params: Json) -> Json {
+  Json::object(vec![("jsonrpc", "2.0".into()), ("method", method.into()), ("params", params)])
+}
+
+fn response(id: &Json, result: RequestResult) -> Json {
+  let outcome = match result {
+    Ok(result)           => ("result", result),
+    Err((code, message)) => {
+      ("error", Json::object(vec![("code", Json::Number(code as f64)), ("message", message.into())]))
+    },
+  };
+
+  Json::object(vec![("jsonrpc", "2.0".into()), ("id", id.clone()), outcome])
+}
+
+fn location(uri: &str, range: Json) -> Json {
+  Json::object(vec![("uri", uri.into()), ("range", range)])
+}
+
+// LSP counts columns in UTF-16 code units where the assembler counts chars
+fn range(line_text: &str, line: usize, column: usize, len: usize) -> Json {
+  let position = |column| Json::object(vec![
+    ("line", line.into()),
+    ("character", line_text.chars().take(column).map(char::len_utf16).sum::<usize>().into()),
+  ]);
+
+  Json::object(vec![("start", position(column)), ("end", position(column + len))])
+}
+
+fn char_column(line_text: &str, utf16_column: usize) -> usize {
+  let mut units = 0;
+
+  for (column, c) in line_text.chars().enumerate() {
+    if units >= utf16_column {
+      return column;
+    }
+    units += c.len_utf16();
+  }
+
+  line_text.chars().count()
+}
+
+// `file:///home/me/a%20b.asm` to `/home/me/a b.asm`; anything but a file URI
+// is kept as it is, which is good enough for a name in diagnostics
+fn uri_to_path(uri: &str) -> String {
+  let path = match uri.strip_prefix("file://") {
+    Some(path) => path,
+    None       => return String::from(uri),
+  };
+
+  let bytes = path.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let hex = path.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+    match (bytes[i], hex) {
+      (b'%', Some(byte)) => {
+        decoded.push(byte);
+        i += 3;
+      },
+      (byte, _)          => {
+        decoded.push(byte);
+        i += 1;
+      },
+    }
+  }
+
+  String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn path_to_uri(path: &str) -> String {
+  let path = Path::new(path);
+  let absolute = match path.canonicalize() {
+    Ok(absolute) => absolute,
+    Err(_)       => path.to_path_buf(),
+  };
+
+  let mut uri = String::from("file://");
+  for byte in absolute.to_string_lossy().bytes() {
+    if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+      uri.push(byte as char);
+    } else {
+      uri.push_str(&format!("%{:02X}", byte));
+    }
+  }
+
+  uri
+}
+
+// one message: `Content-Length` and other headers, a blank line, then the JSON
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+  let mut length = None;
+
+  loop {
+    let mut header = String::new();
+    if input.read_line(&mut header)? == 0 {
+      return Ok(None);
+    }
+
+    let header = header.trim_end();
+    if header.is_empty() {
+      break;
+    }
+
+    if let Some((name, value)) = header.split_once(':') {
+      if name.eq_ignore_ascii_case("Content-Length") {
+        length = value.trim().parse::<usize>().ok();
+      }
+    }
+  }
+
+  let length = match length {
+    Some(length) => length,
+    None         => return Err(io::Error::new(io::ErrorKind::InvalidData, "message without a Content-Length header")),
+  };
+
+  let mut body = vec![0; length];
+  input.read_exact(&mut body)?;
+  Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+fn send(output: &mut impl Write, message: &Json) -> io::Result<()> {
+  let body = message.to_string();
+  write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+  output.flush()
+}
@@ -7,12 +7,13 @@ fn main() {
     let args: Vec<String> = env::args().collect();
 
     let config = Config::new(&args).unwrap_or_else(|err| {
-        println!("Problem parsing arguments: {}", err);
-        process::exit(1);
+        eprintln!("hack_assembler: {}", err);
+        eprintln!("try `hack_assembler --help` for usage");
+        process::exit(2);
     });
 
     if let Err(e) = hack_assembler::run(config) {
-        println!("Application error: {}", e);
+        eprintln!("{}", e);
         process::exit(1);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+
+use crate::error::{AssembleError, Diagnostics, ErrorKind, Span};
+
+// the text format of object files: a `hack-object 1` header, then one line per
+// exported label and one per ROM word, in order. a plain 16-bit binary word is
+// used as it is; any other word says what the linker fills in:
+//
+//   export LOOP 4        LOOP is at address 4 of the module
+//   label 7              address 7 of the module, moved along with it
+//   extern putc 0        the address another object exports putc at, plus 0
+//   variable i 1         the RAM address the linker gives variable i, plus 1
+
+const HEADER: &str = "hack-object 1";
+
+/// One ROM word of an object file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectWord {
+  /// A word that does not depend on where anything ends up.
+  Absolute(u16),
+  /// An address in the module itself, counted from its first word.
+  Label(i64),
+  /// The address of a label exported by another object, plus a constant.
+  Extern(String, i64),
+  /// The RAM address of a variable shared by every object, plus a constant.
+  Variable(String, i64),
+}
+
+// one line of an object file
+impl fmt::Display for ObjectWord {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ObjectWord::Absolute(word)         => write!(f, "{:016b}", word),
+      ObjectWord::Label(address)         => write!(f, "label {}", address),
+      ObjectWord::Extern(name, addend)   => write!(f, "extern {} {}", name, addend),
+      ObjectWord::Variable(name, addend) => write!(f, "variable {} {}", name, addend),
+    }
+  }
+}
+
+/// A module assembled on its own, to be combined with others by [`link`](crate::link).
+#[derive(Debug, Clone)]
+pub struct Object {
+  pub words: Vec<ObjectWord>,
+  /// Where each word came from, indexed like `words`.
+  pub locations: Vec<Span>,
+  /// Labels other objects may refer to, with their address in the module.
+  pub exports: Vec<(String, u16, Span)>,
+  /// Warnings found while assembling.
+  pub warnings: Diagnostics,
+}
+
+impl Object {
+  pub fn to_text(&self) -> String {
+    let mut output = format!("{}\n", HEADER);
+
+    for (name, address, _) in &self.exports {
+      output.push_str(&format!("export {} {}\n", name, address));
+    }
+
+    for word in &self.words {
+      output.push_str(&format!("{}\n", word));
+    }
+
+    output
+  }
+
+  // read back what `to_text` wrote; spans point into `file`
+  pub fn parse(input: &str, file: u32, diagnostics: &mut Diagnostics) -> Object {
+    let mut object = Object {
+      words: Vec::new(),
+      locations: Vec::new(),
+      exports: Vec::new(),
+      warnings: Diagnostics::new(0),
+    };
+
+    let mut header = false;
+    for (index, line) in input.split('\n').enumerate() {
+      let entry = match line.find("//") {
+        Some(comment_index) => &line[..comment_index],
+        None                => line,
+      };
+
+      let text = entry.trim();
+      if text.is_empty() {
+        continue;
+      }
+
+      let indent = entry.len() - entry.trim_start().len();
+      let span = Span::new(index + 1, entry[..indent].chars().count() + 1, text.chars().count()).in_file(file);
+      let malformed = |why: &str| AssembleError::new(ErrorKind::MalformedObject(String::from(why)), span);
+
+      if !header {
+        if text != HEADER {
+          diagnostics.push(malformed("expected a `hack-object 1` header"));
+          return object;
+        }
+
+        header = true;
+        continue;
+      }
+
+      let fields: Vec<&str> = text.split_whitespace().collect();
+      let number = |field: Option<&&str>| field.and_then(|field| field.parse::<i64>().ok());
+
+      let word = match (fields[0], fields.len()) {
+        ("export", 3)   => {
+          match fields[2].parse::<u16>() {
+            Ok(address) => object.exports.push((String::from(fields[1]), address, span)),
+            Err(_)      => diagnostics.push(malformed("expected `export <name> <address>`")),
+          }
+          continue;
+        },
+        ("label", 2)    => number(fields.get(1)).map(ObjectWord::Label),
+        ("extern", 3)   => number(fields.get(2)).map(|addend| ObjectWord::Extern(String::from(fields[1]), addend)),
+        ("variable", 3) => number(fields.get(2)).map(|addend| ObjectWord::Variable(String::from(fields[1]), addend)),
+        (word, 1) if word.len() == 16 && word.chars().all(|c| c == '0' || c == '1') => {
+          Some(ObjectWord::Absolute(u16::from_str_radix(word, 2).unwrap()))
+        },
+        _               => None,
+      };
+
+      match word {
+        Some(word) => {
+          object.words.push(word);
+          object.locations.push(span);
+        },
+        None       => diagnostics.push(malformed("expected a 16-bit word, `label`, `extern`, `variable` or `export`")),
+      }
+    }
+
+    if !header {
+      let span = Span::new(1, 1, 0).in_file(file);
+      diagnostics.push(AssembleError::new(ErrorKind::MalformedObject(String::from("the file is empty")), span));
+    }
+
+    object
+  }
+}
@@ -0,0 +1,360 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::code::{Comp, Dest, Jump};
+use crate::error::Span;
+use crate::expr::Expr;
+use crate::{AInstruction, DataInit, Instruction, Pseudo};
+
+// peephole passes over the parsed program, repeated until none applies.
+// code is only ever changed along its fall-through path or at a jump whose
+// target is known, and labels are never moved or removed, so each label still
+// marks the same point in the program. local `.name` labels are left alone
+// since their meaning depends on the scope they are read in. jumps to
+// anything but a label, such as `@133 0;JMP` or `A=M 0;JMP`, and arithmetic
+// on labels such as `@LOOP+3`, find code by its address rather than by a
+// label, which any change could shift, so a program with either is left as
+// written.
+
+type Code = Vec<(Span, Instruction)>;
+
+// how many words the passes saved
+pub fn optimize(code: &mut Code) -> usize {
+  let labels = labels(code);
+  // `LABEL.local` names a local label from outside its scope
+  let is_label = |name: &str| labels.contains(name) || name.find('.').is_some_and(|dot| labels.contains(&name[dot..]));
+
+  if jumps_off_label(code, &is_label) || uses_label_arithmetic(code, &is_label) {
+    return 0;
+  }
+
+  let before = size(code);
+
+  loop {
+    let changed = remove_unreachable(code)
+      | shorten_jump_chains(code)
+      | drop_jumps_to_next(code)
+      | remove_reloads(code)
+      | remove_dead_loads(code);
+
+    if !changed {
+      break;
+    }
+  }
+
+  before - size(code)
+}
+
+fn size(code: &[(Span, Instruction)]) -> usize {
+  code.iter().map(|(_, instruction)| usize::from(instruction.size())).sum()
+}
+
+fn labels(code: &[(Span, Instruction)]) -> HashSet<&str> {
+  code
+    .iter()
+    .filter_map(|(_, instruction)| match instruction {
+      Instruction::LInstruction(label) => Some(label.as_str()),
+      _                                => None,
+    })
+    .collect()
+}
+
+// whether a jump may go somewhere other than a label, following what A holds
+// along the code. after a label A may hold anything, since it can be reached
+// from elsewhere, and so may A after it is loaded from memory or computed
+fn jumps_off_label(code: &[(Span, Instruction)], is_label: &dyn Fn(&str) -> bool) -> bool {
+  let holds_label = |operand: &AInstruction| match operand {
+    AInstruction::Var(name)                      => is_label(name),
+    AInstruction::Numeric { .. }                 => true,
+    AInstruction::Num(_) | AInstruction::Expr(_) => false,
+  };
+
+  let mut a_is_label = false;
+  for (_, instruction) in code {
+    match instruction {
+      Instruction::AInstruction(operand)                => a_is_label = holds_label(operand),
+      Instruction::CInstruction { dest, jump, .. }      => {
+        if *jump != Jump::Null && !a_is_label {
+          return true;
+        }
+        if writes_a(*dest) {
+          a_is_label = false;
+        }
+      },
+      Instruction::Pseudo(Pseudo::Jump { target, .. })  => {
+        if !holds_label(target) {
+          return true;
+        }
+        a_is_label = true;
+      },
+      // `LOAD D, 0` leaves A alone, so only a label value is known to be in A
+      Instruction::Pseudo(Pseudo::Load { value, .. })   => a_is_label = holds_label(value),
+      Instruction::Pseudo(_)
+      | Instruction::LInstruction(_)
+      | Instruction::NumericLabel(_)                    => a_is_label = false,
+      Instruction::Data { .. }
+      | Instruction::Export(_)
+      | Instruction::Extern(_)                          => (),
+    }
+  }
+
+  false
+}
+
+// whether an operand or data value works out an address from a label
+fn uses_label_arithmetic(code: &[(Span, Instruction)], is_label: &dyn Fn(&str) -> bool) -> bool {
+  code
+    .iter()
+    .flat_map(|(_, instruction)| expressions(instruction))
+    .any(|expr| expr.symbols().iter().any(|(name, _)| is_label(name)))
+}
+
+fn expressions(instruction: &Instruction) -> Vec<&Expr> {
+  match instruction {
+    Instruction::AInstruction(AInstruction::Expr(expr))
+    | Instruction::Pseudo(Pseudo::Load { value: AInstruction::Expr(expr), .. })
+    | Instruction::Pseudo(Pseudo::Jump { target: AInstruction::Expr(expr), .. }) => vec![expr],
+    Instruction::Data { init: DataInit::Values(values), .. } => values.iter().map(|(expr, _, _)| expr).collect(),
+    _ => Vec::new(),
+  }
+}
+
+// anything after an unconditional jump is dead up to the next label
+fn remove_unreachable(code: &mut Code) -> bool {
+  let mut reachable = true;
+  let before = code.len();
+
+  code.retain(|(_, instruction)| match instruction {
+    Instruction::LInstruction(_) | Instruction::NumericLabel(_) => {
+      reachable = true;
+      true
+    },
+    Instruction::Data { .. }
+    | Instruction::Export(_)
+    | Instruction::Extern(_) => true,
+    _ if !reachable          => false,
+    _                        => {
+      reachable = !is_unconditional(instruction);
+      true
+    },
+  });
+
+  code.len() != before
+}
+
+// `@L1 ... ;JMP` where L1 holds only `@L2 0;JMP` goes straight to L2
+fn shorten_jump_chains(code: &mut Code) -> bool {
+  let labels = label_positions(code);
+
+  let forwards: HashMap<String, String> = labels
+    .iter()
+    .filter_map(|(label, &at)| {
+      let i = next_code(code, at)?;
+      let target = match (&code[i].1, code.get(i + 1).map(|(_, next)| next)) {
+        (Instruction::AInstruction(AInstruction::Var(target)), Some(next)) if is_goto(next) => target,
+        (Instruction::Pseudo(Pseudo::Jump { jump: Jump::Jmp, target: AInstruction::Var(target), .. }), _) => target,
+        _ => return None,
+      };
+
+      labels.contains_key(target).then(|| (label.clone(), target.clone()))
+    })
+    .collect();
+
+  let last = |label: &String| {
+    let mut target = label;
+    let mut seen = HashSet::new();
+
+    while let Some(next) = forwards.get(target) {
+      // a loop of jumps never gets anywhere, leave it as written
+      if !seen.insert(target) {
+        return None;
+      }
+      target = next;
+    }
+
+    Some(target.clone()).filter(|target| target != label)
+  };
+
+  let mut retargets = Vec::new();
+  for i in 0..code.len() {
+    let (target, jump, after) = match (&code[i].1, code.get(i + 1).map(|(_, next)| next)) {
+      (Instruction::AInstruction(AInstruction::Var(target)), Some(Instruction::CInstruction { jump, .. }))
+        if *jump != Jump::Null => (target, *jump, i + 2),
+      (Instruction::Pseudo(Pseudo::Jump { target: AInstruction::Var(target), jump, .. }), _) => (target, *jump, i + 1),
+      _ => continue,
+    };
+
+    // when the jump is not taken A now holds the new target, which must not matter
+    if let Some(last) = last(target) {
+      if jump == Jump::Jmp || a_is_dead(code, after) {
+        retargets.push((i, last));
+      }
+    }
+  }
+
+  for (i, last) in &retargets {
+    if let Instruction::AInstruction(AInstruction::Var(target))
+      | Instruction::Pseudo(Pseudo::Jump { target: AInstruction::Var(target), .. }) = &mut code[*i].1
+    {
+      *target = last.clone();
+    }
+  }
+
+  !retargets.is_empty()
+}
+
+// a jump to the instruction after it only has to compute its result
+fn drop_jumps_to_next(code: &mut Code) -> bool {
+  let mut changed = false;
+  let mut i = 0;
+
+  while i < code.len() {
+    let target = match &code[i].1 {
+      Instruction::CInstruction { jump, .. } if *jump != Jump::Null && i > 0 => match &code[i - 1].1 {
+        Instruction::AInstruction(AInstruction::Var(target)) => Some(target),
+        _ => None,
+      },
+      Instruction::Pseudo(Pseudo::Jump { target: AInstruction::Var(target), .. }) => Some(target),
+      _ => None,
+    };
+
+    let to_next = target.is_some_and(|target| labels_before_next_code(code, i + 1).contains(&target));
+    if !to_next {
+      i += 1;
+      continue;
+    }
+
+    // the pseudo-instruction also leaves its target in A
+    let removable = match &code[i].1 {
+      Instruction::CInstruction { dest, .. } => *dest == Dest::Null,
+      _                                      => a_is_dead(code, i + 1),
+    };
+
+    if removable {
+      code.remove(i);
+      changed = true;
+    } else if let Instruction::CInstruction { jump, .. } = &mut code[i].1 {
+      *jump = Jump::Null;
+      changed = true;
+      i += 1;
+    } else {
+      i += 1;
+    }
+  }
+
+  changed
+}
+
+// `@X` when A already holds X, with no label in between that could be jumped to
+fn remove_reloads(code: &mut Code) -> bool {
+  let mut known: Option<AInstruction> = None;
+  let before = code.len();
+
+  code.retain(|(_, instruction)| {
+    match instruction {
+      Instruction::AInstruction(operand) if known.as_ref() == Some(operand) => return false,
+      Instruction::AInstruction(operand) => known = Some(operand.clone()),
+      Instruction::CInstruction { dest, .. } if writes_a(*dest) => known = None,
+      Instruction::CInstruction { .. }
+      | Instruction::Data { .. }
+      | Instruction::Export(_)
+      | Instruction::Extern(_) => (),
+      _ => known = None,
+    }
+
+    true
+  });
+
+  code.len() != before
+}
+
+// `@X` when A is overwritten before anything reads it
+fn remove_dead_loads(code: &mut Code) -> bool {
+  let mut changed = false;
+  let mut i = 0;
+
+  while i < code.len() {
+    if matches!(code[i].1, Instruction::AInstruction(_)) && a_is_dead(code, i + 1) {
+      code.remove(i);
+      changed = true;
+    } else {
+      i += 1;
+    }
+  }
+
+  changed
+}
+
+// whether the code from `from` on, falling through any labels, sets A before reading it
+fn a_is_dead(code: &[(Span, Instruction)], from: usize) -> bool {
+  for (_, instruction) in &code[from.min(code.len())..] {
+    match instruction {
+      Instruction::AInstruction(_) => return true,
+      Instruction::CInstruction { dest, comp, jump } => {
+        if reads_a(*comp) || dest.bits() & 0b001 != 0 || *jump != Jump::Null {
+          return false;
+        }
+        if writes_a(*dest) {
+          return true;
+        }
+      },
+      Instruction::LInstruction(_)
+      | Instruction::NumericLabel(_)
+      | Instruction::Data { .. }
+      | Instruction::Export(_)
+      | Instruction::Extern(_) => (),
+      Instruction::Pseudo(_) => return false,
+    }
+  }
+
+  // nothing runs after the end of the program
+  true
+}
+
+// `M` reads A as an address; raw ALU bits are assumed to read it
+fn reads_a(comp: Comp) -> bool {
+  comp.mnemonic().is_none_or(|mnemonic| mnemonic.contains('A') || mnemonic.contains('M'))
+}
+
+fn writes_a(dest: Dest) -> bool {
+  dest.bits() & 0b100 != 0
+}
+
+// a C-instruction that always jumps and has no other effect
+fn is_goto(instruction: &Instruction) -> bool {
+  matches!(instruction, Instruction::CInstruction { dest: Dest::Null, jump: Jump::Jmp, .. })
+}
+
+fn is_unconditional(instruction: &Instruction) -> bool {
+  matches!(
+    instruction,
+    Instruction::CInstruction { jump: Jump::Jmp, .. } | Instruction::Pseudo(Pseudo::Jump { jump: Jump::Jmp, .. })
+  )
+}
+
+// global labels and where they are defined
+fn label_positions(code: &[(Span, Instruction)]) -> HashMap<String, usize> {
+  code
+    .iter()
+    .enumerate()
+    .filter_map(|(i, (_, instruction))| match instruction {
+      Instruction::LInstruction(label) if !label.starts_with('.') => Some((label.clone(), i)),
+      _ => None,
+    })
+    .collect()
+}
+
+// the global labels between `from` and the next instruction that produces code
+fn labels_before_next_code(code: &[(Span, Instruction)], from: usize) -> Vec<&String> {
+  code[from.min(code.len())..]
+    .iter()
+    .take_while(|(_, instruction)| instruction.size() == 0)
+    .filter_map(|(_, instruction)| match instruction {
+      Instruction::LInstruction(label) if !label.starts_with('.') => Some(label),
+      _ => None,
+    })
+    .collect()
+}
+
+fn next_code(code: &[(Span, Instruction)], from: usize) -> Option<usize> {
+  (from..code.len()).find(|&i| code[i].1.size() > 0)
+}
@@ -0,0 +1,507 @@
+use std::collections::HashMap;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use crate::error::{AssembleError, Diagnostics, ErrorKind, Span};
+use crate::expr::Expr;
+use crate::lexer::{tokenize, Token, TokenKind};
+use crate::source::Sources;
+use crate::syntax::SyntaxTree;
+use crate::{expr_error, is_symbol, sub_span, token_range, Instruction, Options};
+
+// how deep macros may expand other macros before we assume runaway recursion
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+// directives a macro body cannot use, since expanded lines skip directive dispatch
+const NOT_IN_MACROS: [&str; 5] = [".if", ".ifdef", ".else", ".endif", ".include"];
+
+// one line of code with its comment stripped, after macro expansion
+pub struct SourceLine {
+  pub span: Span,
+  pub text: String,
+  // the macro the line came from, if any
+  pub expansion: Option<String>,
+}
+
+impl SourceLine {
+  // point a diagnostic for this line at the macro call it came from
+  pub fn locate(&self, diagnostic: AssembleError) -> AssembleError {
+    match &self.expansion {
+      Some(name) => {
+        let note = format!("in expansion of macro `{}`: `{}`", name, self.text);
+        let mut diagnostic = diagnostic.with_note(note);
+        diagnostic.span = self.span;
+        diagnostic
+      },
+      None => diagnostic,
+    }
+  }
+}
+
+struct Macro {
+  params: Vec<String>,
+  body: Vec<String>,
+  // labels defined in the body, renamed per expansion so each copy has its own
+  labels: Vec<String>,
+  line: usize,
+}
+
+// an `.if` or `.ifdef` still waiting for its `.endif`
+struct Conditional {
+  span: Span,
+  // whether the code around the block is assembled at all
+  enclosing: bool,
+  // whether the branch being read is assembled
+  active: bool,
+  in_else: bool,
+}
+
+struct Preprocessor<'a> {
+  macros: HashMap<String, Macro>,
+  expansions: usize,
+  output: Vec<SourceLine>,
+  sources: &'a mut Sources,
+  options: &'a Options,
+  // the files being read, outermost first, by canonical path and index in `sources`
+  open: Vec<(PathBuf, u32)>,
+}
+
+// expand the first file in `sources`, adding every file it includes
+pub fn preprocess(sources: &mut Sources, options: &Options, diagnostics: &mut Diagnostics) -> Vec<SourceLine> {
+  let mut open = Vec::new();
+  if let Some(path) = sources.get(0).and_then(|source| fs::canonicalize(&source.name).ok()) {
+    open.push((path, 0));
+  }
+
+  let mut preprocessor = Preprocessor {
+    macros: HashMap::new(),
+    expansions: 0,
+    output: Vec::new(),
+    sources,
+    options,
+    open,
+  };
+
+  preprocessor.file(0, diagnostics);
+  preprocessor.output
+}
+
+impl<'a> Preprocessor<'a> {
+  fn file(&mut self, file: u32, diagnostics: &mut Diagnostics) {
+    let input = self.sources.get(file).map(|source| source.text.clone()).unwrap_or_default();
+    let lines = code_lines(&input, file);
+    let mut lines = lines.iter();
+    let mut conditionals: Vec<Conditional> = Vec::new();
+
+    while let Some(&(span, text)) = lines.next() {
+      if diagnostics.is_full() {
+        break;
+      }
+
+      let active = conditionals.last().is_none_or(|conditional| conditional.active);
+
+      match directive(text) {
+        Some(".if") | Some(".ifdef") => {
+          let holds = active && self.condition(span, text, diagnostics);
+          conditionals.push(Conditional { span, enclosing: active, active: holds, in_else: false });
+        },
+        Some(".else")    => match conditionals.last_mut() {
+          Some(conditional) if !conditional.in_else => {
+            conditional.active = conditional.enclosing && !conditional.active;
+            conditional.in_else = true;
+          },
+          Some(_) => {
+            let kind = ErrorKind::MalformedConditional(String::from("a second `.else` for the same `.if`"));
+            diagnostics.push(AssembleError::new(kind, span));
+          },
+          None    => {
+            let kind = ErrorKind::MalformedConditional(String::from("`.else` without a matching `.if`"));
+            diagnostics.push(AssembleError::new(kind, span));
+          },
+        },
+        Some(".endif")   => {
+          if conditionals.pop().is_none() {
+            let kind = ErrorKind::MalformedConditional(String::from("`.endif` without a matching `.if`"));
+            diagnostics.push(AssembleError::new(kind, span));
+          }
+        },
+        // skipped code may be unfinished, so only its nesting is looked at
+        _ if !active     => (),
+        Some(".macro")   => {
+          let mut body = Vec::new();
+          let mut terminated = false;
+
+          for &(line_span, line_text) in lines.by_ref() {
+            if directive(line_text) == Some(".endm") {
+              terminated = true;
+              break;
+            }
+
+            body.push((line_span, line_text));
+          }
+
+          self.define(span, text, body, terminated, diagnostics);
+        },
+        Some(".endm")    => {
+          let kind = ErrorKind::MalformedMacro(String::from("`.endm` without a matching `.macro`"));
+          diagnostics.push(AssembleError::new(kind, span));
+        },
+        Some(".include") => self.include(span, text, diagnostics),
+        // data and linkage directives are assembled along with the instructions
+        Some(".data") | Some(".word") | Some(".string")
+        | Some(".export") | Some(".extern") => self.line(span, text, None, 0, diagnostics),
+        Some(name)       => {
+          let kind = ErrorKind::UnknownDirective(String::from(name));
+          diagnostics.push(AssembleError::new(kind, span.sub(0, name.chars().count())));
+        },
+        None             => self.line(span, text, None, 0, diagnostics),
+      }
+    }
+
+    // blocks cannot span files, so whatever is still open is missing its `.endif`
+    for conditional in conditionals {
+      let kind = ErrorKind::MalformedConditional(String::from("no `.endif` for this block"));
+      diagnostics.push(AssembleError::new(kind, conditional.span));
+    }
+  }
+
+  // whether `.if EXPR` (nonzero) or `.ifdef NAME` holds; a bad condition is reported and fails
+  fn condition(&self, span: Span, text: &str, diagnostics: &mut Diagnostics) -> bool {
+    let tokens = code_tokens(text);
+    let keyword = tokens[0].text;
+    let argument_range = token_range(&tokens[1..], text.len());
+    let argument = &text[argument_range.clone()];
+    let argument_span = sub_span(span, text, argument_range);
+
+    if argument.is_empty() {
+      let kind = ErrorKind::MalformedConditional(format!("`{}` needs a condition", keyword));
+      diagnostics.push(AssembleError::new(kind, span));
+      return false;
+    }
+
+    let lookup = |name: &str| {
+      self.options.defines
+        .iter()
+        .find(|(define, _)| define == name)
+        .map(|(_, value)| *value)
+    };
+
+    if keyword == ".ifdef" {
+      if !is_symbol(argument) {
+        let kind = ErrorKind::MalformedConditional(format!("`.ifdef` takes a symbol, found `{}`", argument));
+        diagnostics.push(AssembleError::new(kind, argument_span));
+        return false;
+      }
+
+      return lookup(argument).is_some();
+    }
+
+    let value = Expr::parse(argument).and_then(|expr| expr.eval(&lookup));
+    match value {
+      Ok(value) => value != 0,
+      Err((ErrorKind::UndefinedInExpression(name), offset, len)) => {
+        let kind = ErrorKind::UndefinedInCondition(name);
+        diagnostics.push(AssembleError::new(kind, argument_span.sub(offset, len)));
+        false
+      },
+      Err(error) => {
+        diagnostics.push(expr_error(error, argument_span));
+        false
+      },
+    }
+  }
+
+  // `.include "path"`, looked up next to the including file and then on the include path
+  fn include(&mut self, span: Span, text: &str, diagnostics: &mut Diagnostics) {
+    let tokens = code_tokens(text);
+    let argument_span = sub_span(span, text, token_range(&tokens[1..], text.len()));
+
+    let quoted = match &tokens[1..] {
+      [argument] if argument.kind == TokenKind::String => argument.text.strip_prefix('"').and_then(|path| path.strip_suffix('"')),
+      _                                                => None,
+    };
+
+    let path = match quoted {
+      Some(path) if !path.is_empty() && !path.contains('"') => path,
+      _ => {
+        let kind = ErrorKind::MalformedInclude(String::from("expected a quoted file name, e.g. `.include \"lib.asm\"`"));
+        return diagnostics.push(AssembleError::new(kind, argument_span));
+      },
+    };
+
+    let including = self.sources.get(span.file).map_or("", |source| source.name.as_str());
+    let base = Path::new(including).parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+
+    let found = iter::once(&base)
+      .chain(&self.options.include_paths)
+      .map(|dir| dir.join(path))
+      .find(|candidate| candidate.is_file());
+
+    let found = match found {
+      Some(found) => found,
+      None        => {
+        let kind = ErrorKind::IncludeNotFound(String::from(path));
+        return diagnostics.push(AssembleError::new(kind, argument_span));
+      },
+    };
+
+    let canonical = fs::canonicalize(&found).unwrap_or_else(|_| found.clone());
+    if let Some(start) = self.open.iter().position(|(open, _)| *open == canonical) {
+      let chain: Vec<String> = self.open[start..]
+        .iter()
+        .filter_map(|&(_, file)| self.sources.get(file).map(|source| source.name.clone()))
+        .chain(iter::once(found.display().to_string()))
+        .collect();
+
+      let kind = ErrorKind::IncludeCycle(chain.join(" -> "));
+      return diagnostics.push(AssembleError::new(kind, argument_span));
+    }
+
+    let input = match fs::read_to_string(&found) {
+      Ok(input) => input,
+      Err(e)    => {
+        let kind = ErrorKind::IncludeFailed(e.to_string());
+        return diagnostics.push(AssembleError::new(kind, argument_span));
+      },
+    };
+
+    let file = self.sources.add(&found.display().to_string(), input, Some(span));
+    self.open.push((canonical, file));
+    self.file(file, diagnostics);
+    self.open.pop();
+  }
+
+  // `header` is the `.macro NAME params` line, `body` everything up to `.endm`
+  fn define(&mut self, span: Span, header: &str, body: Vec<(Span, &str)>, terminated: bool, diagnostics: &mut Diagnostics) {
+    // the name and parameters, after `.macro`, are separated by spaces or commas
+    let words: Vec<&str> = tokenize(header)
+      .split(|token| token.is_trivia() || token.is_punct(","))
+      .filter(|run| !run.is_empty())
+      .skip(1)
+      .map(|run| &header[token_range(run, 0)])
+      .collect();
+
+    let (name, params) = match words.split_first() {
+      Some((name, params)) if is_symbol(name) => (*name, params),
+      _ => {
+        let kind = ErrorKind::MalformedMacro(String::from("`.macro` needs a name"));
+        return diagnostics.push(AssembleError::new(kind, span));
+      },
+    };
+
+    if !terminated {
+      let kind = ErrorKind::MalformedMacro(format!("macro `{}` has no `.endm`", name));
+      diagnostics.push(AssembleError::new(kind, span));
+    }
+
+    if let Some(existing) = self.macros.get(name) {
+      let kind = ErrorKind::DuplicateMacro(String::from(name), existing.line);
+      return diagnostics.push(AssembleError::new(kind, span));
+    }
+
+    let mut valid = true;
+    for (i, param) in params.iter().enumerate() {
+      if !is_symbol(param) || params[..i].contains(param) {
+        let kind = ErrorKind::MalformedMacro(format!("bad parameter `{}`", param));
+        diagnostics.push(AssembleError::new(kind, span));
+        valid = false;
+      }
+    }
+
+    let mut labels = Vec::new();
+    for (line_span, text) in &body {
+      let nested = match directive(text) {
+        Some(".macro")                              => Some(String::from("macros cannot be defined inside a macro")),
+        Some(name) if NOT_IN_MACROS.contains(&name) => Some(format!("`{}` is not allowed inside a macro", name)),
+        _                                           => None,
+      };
+      if let Some(message) = nested {
+        diagnostics.push(AssembleError::new(ErrorKind::MalformedMacro(message), *line_span));
+        valid = false;
+      }
+
+      for (offset, reference) in param_references(text) {
+        if !params.contains(&reference) {
+          let kind = ErrorKind::UnknownMacroParam(String::from(reference));
+          diagnostics.push(AssembleError::new(kind, line_span.sub(offset, reference.chars().count() + 1)));
+          valid = false;
+        }
+      }
+
+      if let Ok(Instruction::LInstruction(label)) = Instruction::get(text, *line_span) {
+        labels.push(label);
+      }
+    }
+
+    // a broken definition is still recorded so its calls are not reported as well
+    let body = if valid {
+      body.iter().map(|(_, text)| String::from(*text)).collect()
+    } else {
+      Vec::new()
+    };
+
+    self.macros.insert(String::from(name), Macro {
+      params: params.iter().map(|param| String::from(*param)).collect(),
+      body,
+      labels,
+      line: span.line,
+    });
+  }
+
+  fn line(&mut self, span: Span, text: &str, expansion: Option<&str>, depth: usize, diagnostics: &mut Diagnostics) {
+    // a call's name runs up to the first space, so `D=M` never calls a macro `D`
+    let tokens = tokenize(text);
+    let name_end = tokens.iter().position(|token| token.is_trivia()).unwrap_or(tokens.len());
+    let name = &text[token_range(&tokens[..name_end], 0)];
+
+    if !self.macros.contains_key(name) {
+      self.output.push(SourceLine {
+        span,
+        text: String::from(text),
+        expansion: expansion.map(String::from),
+      });
+      return;
+    }
+
+    let located = |diagnostic: AssembleError| match expansion {
+      Some(outer) => diagnostic.with_note(format!("in expansion of macro `{}`", outer)),
+      None        => diagnostic,
+    };
+
+    if depth >= MAX_EXPANSION_DEPTH {
+      let kind = ErrorKind::MacroRecursion(String::from(name));
+      return diagnostics.push(located(AssembleError::new(kind, span)));
+    }
+
+    // commas in string and char literals do not separate arguments
+    let rest = &text[name.len()..];
+    let args: Vec<&str> = match code_tokens(rest).as_slice() {
+      []     => Vec::new(),
+      tokens => tokens.split(|token| token.is_punct(",")).map(|arg| &rest[token_range(arg, 0)]).collect(),
+    };
+
+    let definition = &self.macros[name];
+    if args.len() != definition.params.len() {
+      let kind = ErrorKind::MacroArity(String::from(name), definition.params.len(), args.len());
+      return diagnostics.push(located(AssembleError::new(kind, span)));
+    }
+
+    self.expansions += 1;
+    let suffix = format!("${}", self.expansions);
+
+    let params: HashMap<&str, &str> = definition.params
+      .iter()
+      .map(String::as_str)
+      .zip(args)
+      .collect();
+
+    let expanded: Vec<String> = definition.body
+      .iter()
+      .map(|line| {
+        let line = substitute_params(line, &params);
+        rename_symbols(&line, |symbol| {
+          definition.labels
+            .iter()
+            .any(|label| label == symbol)
+            .then(|| format!("{}{}", symbol, suffix))
+        })
+      })
+      .collect();
+
+    for line in expanded {
+      self.line(span.expanded(), &line, Some(name), depth + 1, diagnostics);
+    }
+  }
+}
+
+// every line with code, without its comment, and a span over the code
+fn code_lines(input: &str, file: u32) -> Vec<(Span, &str)> {
+  SyntaxTree::parse(input)
+    .lines
+    .iter()
+    .filter(|line| !line.code().is_empty())
+    .map(|line| (line.span().in_file(file), line.code()))
+    .collect()
+}
+
+// the tokens of a line of code, less whitespace
+fn code_tokens(text: &str) -> Vec<Token<'_>> {
+  tokenize(text).into_iter().filter(|token| !token.is_trivia()).collect()
+}
+
+// the directive a line starts with, e.g. `.macro`
+fn directive(text: &str) -> Option<&str> {
+  let first = code_tokens(text).into_iter().next()?;
+  let name = first.text.strip_prefix('.')?;
+
+  match name.chars().next() {
+    Some(c) if first.kind == TokenKind::Word && c.is_ascii_alphabetic() => Some(first.text),
+    _                                                                   => None,
+  }
+}
+
+// call `f` on each word of the code outside quotes with its char offset, its
+// byte offset and whether it follows a `\`
+fn for_each_word<'a>(text: &'a str, mut f: impl FnMut(usize, usize, &'a str, bool)) {
+  let tokens = tokenize(text);
+
+  for (i, token) in tokens.iter().enumerate() {
+    if token.kind != TokenKind::Word {
+      continue;
+    }
+
+    let escaped = i > 0 && tokens[i - 1].is_punct("\\");
+    f(text[..token.span.start].chars().count(), token.span.start, token.text, escaped);
+  }
+}
+
+// `\param` references with the char offset of their `\`
+fn param_references(text: &str) -> Vec<(usize, &str)> {
+  let mut references = Vec::new();
+
+  for_each_word(text, |offset, _, word, escaped| {
+    if escaped {
+      references.push((offset - 1, word));
+    }
+  });
+
+  references
+}
+
+fn substitute_params(text: &str, params: &HashMap<&str, &str>) -> String {
+  let mut output = String::new();
+  let mut copied = 0;
+
+  for_each_word(text, |_, start, word, escaped| {
+    if let (true, Some(arg)) = (escaped, params.get(word)) {
+      output.push_str(&text[copied..start - 1]);
+      output.push_str(arg);
+      copied = start + word.len();
+    }
+  });
+
+  output.push_str(&text[copied..]);
+  output
+}
+
+// replace whole symbols for which `rename` gives a new name
+pub fn rename_symbols(text: &str, rename: impl Fn(&str) -> Option<String>) -> String {
+  let mut output = String::new();
+  let mut copied = 0;
+
+  for_each_word(text, |_, start, word, escaped| {
+    if escaped || word.starts_with(|c: char| c.is_ascii_digit()) {
+      return;
+    }
+
+    if let Some(new_name) = rename(word) {
+      output.push_str(&text[copied..start]);
+      output.push_str(&new_name);
+      copied = start + word.len();
+    }
+  });
+
+  output.push_str(&text[copied..]);
+  output
+}
@@ -0,0 +1,49 @@
+use crate::error::Span;
+
+/// A file taking part in an assembly, either the main input or an `.include`.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+  pub name: String,
+  pub text: String,
+  /// The `.include` directive that pulled the file in; None for the main input.
+  pub included_from: Option<Span>,
+}
+
+/// Every file read while assembling, indexed by `Span::file`.
+#[derive(Debug, Clone, Default)]
+pub struct Sources {
+  files: Vec<SourceFile>,
+}
+
+impl Sources {
+  pub fn add(&mut self, name: &str, text: String, included_from: Option<Span>) -> u32 {
+    self.files.push(SourceFile {
+      name: String::from(name),
+      text,
+      included_from,
+    });
+
+    self.files.len() as u32 - 1
+  }
+
+  pub fn get(&self, file: u32) -> Option<&SourceFile> {
+    self.files.get(file as usize)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &SourceFile> {
+    self.files.iter()
+  }
+
+  // the `.include` directives leading to `file`, innermost first
+  pub fn include_chain(&self, file: u32) -> Vec<Span> {
+    let mut chain = Vec::new();
+    let mut current = self.get(file).and_then(|source| source.included_from);
+
+    while let Some(span) = current {
+      chain.push(span);
+      current = self.get(span.file).and_then(|source| source.included_from);
+    }
+
+    chain
+  }
+}
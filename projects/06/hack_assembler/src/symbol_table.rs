@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::Span;
+use crate::json::Json;
+use crate::source::Sources;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SymbolKind {
+  Predefined,
+  Defined,
+  Label,
+  Data,
+  Variable,
+}
+
+impl fmt::Display for SymbolKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SymbolKind::Predefined => f.pad("predefined"),
+      SymbolKind::Defined    => f.pad("defined"),
+      SymbolKind::Label      => f.pad("label"),
+      SymbolKind::Data       => f.pad("data"),
+      SymbolKind::Variable   => f.pad("variable"),
+    }
+  }
+}
+
+/// A named address: a ROM address for labels, a RAM address otherwise.
+/// Symbols defined on the command line hold whatever value they were given.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+  pub name: String,
+  pub kind: SymbolKind,
+  pub address: u16,
+  /// Where a label is defined or a variable first allocated; None when predefined
+  /// or defined on the command line.
+  pub span: Option<Span>,
+  /// The first A-instruction that refers to the symbol, if any.
+  pub first_use: Option<Span>,
+}
+
+/// Every symbol a program can refer to, including the predefined ones.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+  symbols: HashMap<String, Symbol>,
+}
+
+impl SymbolTable {
+  pub fn get(&self, name: &str) -> Option<&Symbol> {
+    self.symbols.get(name)
+  }
+
+  pub fn address(&self, name: &str) -> Option<u16> {
+    self.get(name).map(|symbol| symbol.address)
+  }
+
+  pub fn contains(&self, name: &str) -> bool {
+    self.symbols.contains_key(name)
+  }
+
+  /// Symbols ordered by kind, then address, then name.
+  pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+    let mut symbols: Vec<&Symbol> = self.symbols.values().collect();
+    symbols.sort_by(|a, b| (a.kind, a.address, &a.name).cmp(&(b.kind, b.address, &b.name)));
+    symbols.into_iter()
+  }
+
+  pub fn len(&self) -> usize {
+    self.symbols.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.symbols.is_empty()
+  }
+
+  pub(crate) fn mark_use(&mut self, name: &str, span: Span) {
+    if let Some(symbol) = self.symbols.get_mut(name) {
+      symbol.first_use.get_or_insert(span);
+    }
+  }
+
+  /// One `<kind> <address> <name> <first use>` row per symbol, the format
+  /// the disassembler reads back with `--symbol-file`. The first use is
+  /// `file:line`, with file names from `sources`.
+  pub fn to_sym(&self, sources: &Sources) -> String {
+    let mut output = format!("// {:<11}{:<9}{:<24}{}\n", "kind", "address", "name", "first use");
+
+    for symbol in self.iter() {
+      let first_use = match symbol.first_use {
+        Some(span) => location(span, sources),
+        None       => String::from("-"),
+      };
+
+      output.push_str(&format!("{:<14}{:<9}{:<24}{}\n", symbol.kind, symbol.address, symbol.name, first_use));
+    }
+
+    output
+  }
+
+  // one symbol per line, each a JSON object like the rows of `to_sym`
+  pub fn to_json(&self, sources: &Sources) -> String {
+    let entries: Vec<String> = self.iter()
+      .map(|symbol| {
+        let first_use = match symbol.first_use {
+          Some(span) => Json::from(location(span, sources)),
+          None       => Json::Null,
+        };
+
+        let entry = Json::object(vec![
+          ("name", Json::from(symbol.name.as_str())),
+          ("kind", Json::from(symbol.kind.to_string())),
+          ("address", Json::from(usize::from(symbol.address))),
+          ("first_use", first_use),
+        ]);
+        format!("    {}", entry)
+      })
+      .collect();
+
+    format!("{{\n  \"symbols\": [\n{}\n  ]\n}}\n", entries.join(",\n"))
+  }
+
+  pub(crate) fn predefine(&mut self, name: &str, address: u16) {
+    self.add(name, SymbolKind::Predefined, address, None);
+  }
+
+  pub(crate) fn define(&mut self, name: &str, value: u16) {
+    self.add(name, SymbolKind::Defined, value, None);
+  }
+
+  pub(crate) fn insert(&mut self, name: &str, kind: SymbolKind, address: u16, span: Span) {
+    self.add(name, kind, address, Some(span));
+  }
+
+  fn add(&mut self, name: &str, kind: SymbolKind, address: u16, span: Option<Span>) {
+    let symbol = Symbol {
+      name: String::from(name),
+      kind,
+      address,
+      span,
+      first_use: None,
+    };
+
+    self.symbols.insert(symbol.name.clone(), symbol);
+  }
+}
+
+// `file:line`, as diagnostics point at code
+fn location(span: Span, sources: &Sources) -> String {
+  let file = sources.get(span.file).map_or("<input>", |source| source.name.as_str());
+  format!("{}:{}", file, span.line)
+}
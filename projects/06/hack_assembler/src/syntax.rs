@@ -0,0 +1,176 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::error::Span;
+use crate::lexer::{tokenize, Token, TokenKind};
+use crate::parse_digits;
+
+// block directives of the preprocessor, which give a file its structure
+const BLOCK_DIRECTIVES: [&str; 7] = [".if", ".ifdef", ".else", ".endif", ".macro", ".endm", ".include"];
+
+/// What a line of assembly holds, judged from its code alone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineKind {
+  /// Nothing but whitespace.
+  Blank,
+  /// A comment with no code before it.
+  Comment,
+  /// `(NAME)` or a numeric label such as `1:`.
+  Label,
+  /// `.if`, `.macro`, `.include` and the other preprocessor block directives.
+  Directive,
+  /// Anything else: an instruction, a data directive or a macro call.
+  Code,
+}
+
+/// One line of source as tokens, not counting its line break.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxLine<'a> {
+  pub kind: LineKind,
+  /// The 1-based line number.
+  pub number: usize,
+  pub tokens: Vec<Token<'a>>,
+  /// The line break ending the line; None on the last line.
+  pub newline: Option<Token<'a>>,
+  source: &'a str,
+  // byte offset of the line's first char, after any byte order mark
+  start: usize,
+}
+
+/// A source file as lines of tokens, keeping the comments and whitespace the
+/// assembler ignores, so the file can be written back unchanged or reformatted.
+/// This is the one place source text is split into lines and code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree<'a> {
+  /// A byte order mark the source started with.
+  pub bom: Option<Token<'a>>,
+  pub lines: Vec<SyntaxLine<'a>>,
+}
+
+impl<'a> SyntaxTree<'a> {
+  pub fn parse(source: &'a str) -> SyntaxTree<'a> {
+    let mut tokens = tokenize(source).into_iter().peekable();
+    let bom = tokens.next_if(|token| token.kind == TokenKind::Bom);
+
+    let mut lines = Vec::new();
+    let mut start = bom.as_ref().map_or(0, |bom| bom.span.end);
+    loop {
+      let mut line = Vec::new();
+      let newline = loop {
+        match tokens.next() {
+          Some(token) if token.kind == TokenKind::Newline => break Some(token),
+          Some(token)                                     => line.push(token),
+          None                                            => break None,
+        }
+      };
+
+      let next_start = newline.as_ref().map(|newline| newline.span.end);
+      lines.push(SyntaxLine {
+        kind: kind(&line),
+        number: lines.len() + 1,
+        tokens: line,
+        newline,
+        source,
+        start,
+      });
+
+      match next_start {
+        Some(next_start) => start = next_start,
+        None             => break,
+      }
+    }
+
+    SyntaxTree { bom, lines }
+  }
+
+  // whether lines end with `\r\n`, judged by the first line
+  pub fn is_crlf(&self) -> bool {
+    self.lines[0].newline.as_ref().is_some_and(|newline| newline.text == "\r\n")
+  }
+}
+
+impl<'a> SyntaxLine<'a> {
+  /// The tokens the assembler reads: no whitespace or comments.
+  pub fn code_tokens(&self) -> impl Iterator<Item = &Token<'a>> {
+    self.tokens.iter().filter(|token| !token.is_trivia())
+  }
+
+  /// The byte range of the code, from its first token to its last.
+  pub fn code_range(&self) -> Range<usize> {
+    let mut code = self.code_tokens();
+    match (code.next(), code.last()) {
+      (Some(first), Some(last)) => first.span.start..last.span.end,
+      (Some(first), None)       => first.span.clone(),
+      _                         => self.start..self.start,
+    }
+  }
+
+  pub fn code(&self) -> &'a str {
+    &self.source[self.code_range()]
+  }
+
+  /// The whitespace before the code or comment.
+  pub fn indent(&self) -> &'a str {
+    match self.tokens.first() {
+      Some(token) if token.kind == TokenKind::Whitespace => token.text,
+      _                                                  => "",
+    }
+  }
+
+  pub fn comment(&self) -> Option<&'a str> {
+    self.tokens.iter().find(|token| token.kind == TokenKind::Comment).map(|token| token.text)
+  }
+
+  /// The 1-based char column of a byte offset in the line.
+  pub fn column(&self, offset: usize) -> usize {
+    self.source[self.start..offset].chars().count() + 1
+  }
+
+  /// A span over the code, for diagnostics.
+  pub fn span(&self) -> Span {
+    let range = self.code_range();
+    Span::new(self.number, self.column(range.start), self.source[range].chars().count())
+  }
+}
+
+fn kind(tokens: &[Token]) -> LineKind {
+  let mut code = tokens.iter().filter(|token| !token.is_trivia());
+
+  match code.next() {
+    None if tokens.iter().any(|token| token.kind == TokenKind::Comment) => LineKind::Comment,
+    None                                                                => LineKind::Blank,
+    Some(first) if first.is_punct("(") || is_numeric_label(first)      => LineKind::Label,
+    Some(first) if BLOCK_DIRECTIVES.contains(&first.text)             => LineKind::Directive,
+    Some(_)                                                             => LineKind::Code,
+  }
+}
+
+// `1:`, which is a single word since `:` is a symbol char
+pub fn is_numeric_label(token: &Token) -> bool {
+  token.kind == TokenKind::Word && token.text.strip_suffix(':').and_then(parse_digits).is_some()
+}
+
+impl<'a> fmt::Display for SyntaxLine<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for token in self.tokens.iter().chain(&self.newline) {
+      write!(f, "{}", token.text)?;
+    }
+
+    Ok(())
+  }
+}
+
+// the source exactly as parsed
+impl<'a> fmt::Display for SyntaxTree<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if let Some(bom) = &self.bom {
+      write!(f, "{}", bom.text)?;
+    }
+
+    for line in &self.lines {
+      write!(f, "{}", line)?;
+    }
+
+    Ok(())
+  }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+use crate::code::Jump;
+use crate::error::{AssembleError, Diagnostics, ErrorKind, Severity, Span, Suspicion};
+use crate::symbol_table::{SymbolKind, SymbolTable};
+use crate::{resolve_local, AInstruction, Instruction, Pseudo};
+
+// any unknown name becomes a variable, so a misspelt label assembles without
+// complaint. this warns about the variables that look like one: those jumped
+// to, those close to the name of a label and those only used once.
+// `scopes` holds the scope of each instruction, for `.name` references
+pub fn check_variables(
+  code: &[(Span, Instruction)],
+  scopes: &[String],
+  symbols: &SymbolTable,
+  strict: bool,
+  diagnostics: &mut Diagnostics,
+) {
+  let mut uses: HashMap<String, usize> = HashMap::new();
+  let mut jumps: HashMap<String, Span> = HashMap::new();
+
+  for (i, ((span, instruction), scope)) in code.iter().zip(scopes).enumerate() {
+    for operand in operands(instruction) {
+      for name in names(operand) {
+        *uses.entry(resolve_local(symbols, scope, name)).or_default() += 1;
+      }
+    }
+
+    // `@name` right before a jump, or the target of a jump pseudo-instruction
+    let (target, offset) = match (instruction, code.get(i + 1).map(|(_, next)| next)) {
+      (Instruction::AInstruction(AInstruction::Var(name)), Some(Instruction::CInstruction { jump, .. }))
+        if *jump != Jump::Null => (name, 1),
+      (Instruction::Pseudo(Pseudo::Jump { target: AInstruction::Var(name), offset, .. }), _) => (name, *offset),
+      _ => continue,
+    };
+
+    let operand = span.sub(offset, target.chars().count());
+    jumps.entry(resolve_local(symbols, scope, target)).or_insert(operand);
+  }
+
+  let labels: Vec<&str> = symbols
+    .iter()
+    .filter(|symbol| symbol.kind == SymbolKind::Label)
+    .map(|symbol| symbol.name.as_str())
+    .collect();
+
+  for symbol in symbols.iter().filter(|symbol| symbol.kind == SymbolKind::Variable) {
+    let name = &symbol.name;
+    let first_use = symbol.span.unwrap_or_else(|| Span::new(1, 1, 0));
+    let near = closest(name, &labels);
+
+    let (suspicion, span) = if let Some(span) = jumps.get(name) {
+      (Suspicion::JumpTarget, *span)
+    } else if near.is_some() {
+      (Suspicion::NearLabel, first_use)
+    } else if uses.get(name).copied().unwrap_or(0) == 1 {
+      (Suspicion::UsedOnce, first_use)
+    } else {
+      continue;
+    };
+
+    let mut diagnostic = AssembleError::new(ErrorKind::SuspiciousVariable(name.clone(), suspicion), span);
+    if let Some(label) = near {
+      diagnostic = diagnostic.with_note(format!("did you mean the label `{}`?", label));
+    }
+    if strict {
+      diagnostic.severity = Severity::Error;
+    }
+
+    diagnostics.push(diagnostic);
+  }
+}
+
+fn operands(instruction: &Instruction) -> Vec<&AInstruction> {
+  match instruction {
+    Instruction::AInstruction(operand)                    => vec![operand],
+    Instruction::Pseudo(Pseudo::Load { value, .. })       => vec![value],
+    Instruction::Pseudo(Pseudo::Jump { target, .. })      => vec![target],
+    _                                                     => Vec::new(),
+  }
+}
+
+fn names(operand: &AInstruction) -> Vec<&str> {
+  match operand {
+    AInstruction::Var(name)  => vec![name.as_str()],
+    AInstruction::Expr(expr) => expr.symbols().into_iter().map(|(name, _)| name).collect(),
+    _                        => Vec::new(),
+  }
+}
+
+// the label `name` is most likely a misspelling of, ignoring case. short names
+// are too alike to tell, and longer ones may be off by more than one letter
+fn closest<'a>(name: &str, labels: &[&'a str]) -> Option<&'a str> {
+  let len = name.chars().count();
+  if len < 3 {
+    return None;
+  }
+
+  let allowed = if len >= 8 { 2 } else { 1 };
+  let name = name.to_lowercase();
+
+  labels
+    .iter()
+    .map(|label| (edit_distance(&name, &label.to_lowercase()), *label))
+    .filter(|(distance, _)| *distance <= allowed)
+    .min()
+    .map(|(_, label)| label)
+}
+
+// the Levenshtein distance: how many chars to insert, delete or replace
+fn edit_distance(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut row: Vec<usize> = (0..=b.len()).collect();
+
+  for (i, ca) in a.chars().enumerate() {
+    let mut diagonal = row[0];
+    row[0] = i + 1;
+
+    for (j, cb) in b.iter().enumerate() {
+      let replace = diagonal + usize::from(ca != *cb);
+      diagonal = row[j + 1];
+      row[j + 1] = replace.min(row[j] + 1).min(diagonal + 1);
+    }
+  }
+
+  row[b.len()]
+}
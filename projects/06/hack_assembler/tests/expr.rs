@@ -0,0 +1,63 @@
+use hack_assembler::{assemble, assemble_object, ErrorKind, Options, Span};
+
+fn value(expr: &str) -> u16 {
+  assemble(&format!("@{}\n", expr), &Options::default()).expect("assembles").words[0]
+}
+
+fn error(source: &str) -> (ErrorKind, Span) {
+  let diagnostics = assemble(source, &Options::default()).expect_err("fails");
+  let first = diagnostics.iter().next().expect("a diagnostic");
+  (first.kind.clone(), first.span)
+}
+
+// an object file reports label arithmetic it cannot relocate by writing the
+// parsed expression back out
+fn unrelocatable(expr: &str) -> String {
+  let source = format!("(L)\n(M)\n@{}\n", expr);
+  let diagnostics = assemble_object(&source, &Options::default()).expect_err("fails");
+  let kind = diagnostics.iter().next().expect("a diagnostic").kind.clone();
+
+  match kind {
+    ErrorKind::NotRelocatable(expr) => expr,
+    kind                            => panic!("expected NotRelocatable, found {:?}", kind),
+  }
+}
+
+#[test]
+fn operators_bind_by_precedence() {
+  assert_eq!(value("1 + 2 * 3 - 4 / 2"), 5);
+  assert_eq!(value("-(2 - 5) * (1 + 1)"), 6);
+  assert_eq!(value("2 * -3 + 10"), 4);
+}
+
+#[test]
+fn operators_associate_left() {
+  assert_eq!(value("10-2-3"), 5);
+  assert_eq!(value("100/10/5"), 2);
+}
+
+#[test]
+fn errors_point_at_the_offending_token() {
+  let bad = |message: &str| ErrorKind::BadExpression(String::from(message));
+
+  assert_eq!(error("@1+*2\n"), (bad("expected a value, found `*`"), Span::new(1, 4, 1)));
+  assert_eq!(error("@1 +\n"), (bad("expected a value"), Span::new(1, 5, 1)));
+  assert_eq!(error("@(1+2\n"), (bad("unclosed `(`"), Span::new(1, 2, 1)));
+  assert_eq!(error("D=A\n@2 * (3 +\n"), (bad("expected a value"), Span::new(2, 10, 1)));
+}
+
+#[test]
+fn display_keeps_needed_parentheses() {
+  assert_eq!(unrelocatable("L-(M-1)"), "L-(M-1)");
+  assert_eq!(unrelocatable("-(L+1)"), "-(L+1)");
+  assert_eq!(unrelocatable("2*(L+1)"), "2*(L+1)");
+  assert_eq!(unrelocatable("(L+1)*2"), "(L+1)*2");
+  assert_eq!(unrelocatable("L/(4/2)"), "L/(4/2)");
+}
+
+#[test]
+fn display_drops_redundant_parentheses() {
+  assert_eq!(unrelocatable("((L)) * 2"), "L*2");
+  assert_eq!(unrelocatable("(L*2)-M"), "L*2-M");
+  assert_eq!(unrelocatable("(L * (M))"), "L*M");
+}
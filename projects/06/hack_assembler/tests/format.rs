@@ -0,0 +1,86 @@
+use hack_assembler::{read_machine_code, write_machine_code, Diagnostics, ErrorKind, Format, Span};
+
+const FORMATS: [Format; 7] = [
+  Format::Hack,
+  Format::BinaryBigEndian,
+  Format::BinaryLittleEndian,
+  Format::IntelHex,
+  Format::ReadmemB,
+  Format::ReadmemH,
+  Format::Logisim,
+];
+
+// the words of a text input, and what went wrong reading them
+fn load(input: &str, format: Format) -> (Vec<u16>, Vec<Span>, Vec<(ErrorKind, Span)>) {
+  let mut diagnostics = Diagnostics::new(0);
+  let (words, locations) = read_machine_code(input.as_bytes(), format, &mut diagnostics);
+  let errors = diagnostics.iter().map(|diagnostic| (diagnostic.kind.clone(), diagnostic.span)).collect();
+
+  (words, locations, errors)
+}
+
+#[test]
+fn every_format_reads_back_what_it_writes() {
+  let programs: [&[u16]; 4] = [&[], &[0], &[0x7fff, 0xec10, 0xffff, 0x0001, 0x8000], &[0xe308; 20]];
+
+  for format in FORMATS {
+    for words in programs {
+      let mut diagnostics = Diagnostics::new(0);
+      let (read_words, locations) = read_machine_code(&write_machine_code(words, format), format, &mut diagnostics);
+
+      assert!(!diagnostics.has_errors(), "{:?}: {}", format, diagnostics);
+      assert_eq!(read_words, words, "{:?}", format);
+      assert_eq!(locations.len(), words.len(), "{:?}", format);
+    }
+  }
+}
+
+#[test]
+fn binary_formats_differ_in_byte_order() {
+  assert_eq!(write_machine_code(&[0x1234], Format::BinaryBigEndian), vec![0x12, 0x34]);
+  assert_eq!(write_machine_code(&[0x1234], Format::BinaryLittleEndian), vec![0x34, 0x12]);
+
+  let mut diagnostics = Diagnostics::new(0);
+  let (words, _) = read_machine_code(&[0x12, 0x34, 0x56], Format::BinaryBigEndian, &mut diagnostics);
+  assert_eq!(words, vec![0x1234]);
+  assert!(diagnostics.has_errors());
+}
+
+#[test]
+fn intel_hex_records_are_checked() {
+  assert_eq!(write_machine_code(&[0xec10], Format::IntelHex), b":02000000EC1002\n:00000001FF\n".to_vec());
+
+  let (words, locations, errors) = load(":02000000EC1002\n:02000100EC1002\n:0200020000\n:00000001FF\n", Format::IntelHex);
+  assert_eq!(words, vec![0xec10]);
+  assert_eq!(locations, vec![Span::new(1, 1, 15)]);
+  assert_eq!(errors, vec![
+    (ErrorKind::MalformedMachineCode(String::from("bad checksum")), Span::new(2, 1, 15)),
+    (ErrorKind::MalformedMachineCode(String::from("the byte count does not match the record's length")), Span::new(3, 1, 11)),
+  ]);
+}
+
+// diagnostics can quote the line a word came from, whatever the layout
+#[test]
+fn text_loaders_know_where_each_word_is() {
+  let (words, locations, _) = load("0000000000000001\n\n  1110110000010000\n", Format::Hack);
+  assert_eq!(words, vec![1, 0xec10]);
+  assert_eq!(locations, vec![Span::new(1, 1, 16), Span::new(3, 3, 16)]);
+
+  let (words, locations, _) = load("// two words\n0001 @3 ec10\n", Format::ReadmemH);
+  assert_eq!(words, vec![1, 0, 0, 0xec10]);
+  assert_eq!(locations[0], Span::new(2, 1, 4));
+  assert_eq!(locations[3], Span::new(2, 9, 4));
+
+  let (words, locations, _) = load("v2.0 raw\n1 2*ec10 # comment\n", Format::Logisim);
+  assert_eq!(words, vec![1, 0xec10, 0xec10]);
+  assert_eq!(locations, vec![Span::new(2, 1, 1), Span::new(2, 3, 6), Span::new(2, 3, 6)]);
+}
+
+#[test]
+fn malformed_words_point_at_their_text() {
+  let (_, _, errors) = load("0000000000000001\n  10201\n", Format::Hack);
+  assert_eq!(errors, vec![(ErrorKind::MalformedWord(String::from("10201")), Span::new(2, 3, 5))]);
+
+  let (_, _, errors) = load("0000_0000 1x\n", Format::ReadmemB);
+  assert_eq!(errors, vec![(ErrorKind::MalformedMachineCode(String::from("`1x` is not a 16-bit word in binary")), Span::new(1, 11, 2))]);
+}
@@ -0,0 +1,37 @@
+use hack_assembler::{assemble, format_source, Options};
+
+const SOURCES: [&str; 8] = [
+  include_str!("../add.asm"),
+  include_str!("../max.asm"),
+  include_str!("../maxL.asm"),
+  include_str!("../rect.asm"),
+  include_str!("../rectL.asm"),
+  include_str!("../pongL.asm"),
+  "\u{feff}// crlf\r\n  @x//c\r\n\r\n\r\n(L)\r\n  M = M + D ; JGT\r\n",
+  ".macro INC reg\n@\\reg\n  M=M+1 // bump\n.endm\n\n\n INC x   // once\n  bad junk\n",
+];
+
+#[test]
+fn formatting_is_idempotent() {
+  for source in SOURCES {
+    let once = format_source(source);
+
+    assert_eq!(format_source(&once), once);
+  }
+}
+
+#[test]
+fn formatting_keeps_the_program() {
+  for source in SOURCES.iter().filter(|source| !source.contains("junk")) {
+    let options = Options::default();
+
+    assert_eq!(assemble(&format_source(source), &options).unwrap().words, assemble(source, &options).unwrap().words);
+  }
+}
+
+#[test]
+fn formatting_keeps_line_endings_and_bom() {
+  let formatted = format_source(SOURCES[6]);
+
+  assert_eq!(formatted, "\u{feff}    // crlf\r\n    @x  //c\r\n\r\n(L)\r\n    M=D+M;JGT\r\n");
+}
@@ -0,0 +1,113 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use hack_assembler::{assemble, listing, Diagnostics, ErrorKind, Options, Program};
+
+// a fresh directory holding `files`, each a path relative to it and its text
+fn fixture(name: &str, files: &[(&str, &str)]) -> PathBuf {
+  let dir = std::env::temp_dir().join(format!("hack_assembler_{}_{}", name, process::id()));
+  let _ = fs::remove_dir_all(&dir);
+
+  for (path, text) in files {
+    let path = dir.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, text).unwrap();
+  }
+
+  dir
+}
+
+fn assemble_file(path: &Path) -> Result<Program, Diagnostics> {
+  let options = Options {
+    file_name: path.display().to_string(),
+    ..Options::default()
+  };
+
+  assemble(&fs::read_to_string(path).unwrap(), &options)
+}
+
+fn errors(path: &Path) -> Vec<ErrorKind> {
+  match assemble_file(path) {
+    Ok(_)            => Vec::new(),
+    Err(diagnostics) => diagnostics.iter().map(|diagnostic| diagnostic.kind.clone()).collect(),
+  }
+}
+
+#[test]
+fn includes_are_found_next_to_the_including_file() {
+  let dir = fixture("nested", &[
+    ("main.asm", "@1\n.include \"sub/lib.asm\"\n@4\n"),
+    ("sub/lib.asm", "@2\n.include \"deeper/more.asm\"\n"),
+    ("sub/deeper/more.asm", "@3\n"),
+  ]);
+
+  assert_eq!(assemble_file(&dir.join("main.asm")).unwrap().words, vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn missing_includes_are_errors() {
+  let dir = fixture("missing", &[
+    ("main.asm", ".include \"sub/lib.asm\"\n"),
+    ("sub/lib.asm", ".include \"main.asm\"\n.include \"nope.asm\"\n"),
+  ]);
+
+  // a path is relative to the file it is written in, not to the main input
+  assert_eq!(errors(&dir.join("main.asm")), vec![
+    ErrorKind::IncludeNotFound(String::from("main.asm")),
+    ErrorKind::IncludeNotFound(String::from("nope.asm")),
+  ]);
+}
+
+#[test]
+fn include_cycles_are_errors() {
+  let dir = fixture("cycle", &[
+    ("main.asm", ".include \"a.asm\"\n"),
+    ("a.asm", "@1\n.include \"sub/b.asm\"\n"),
+    ("sub/b.asm", ".include \"../a.asm\"\n"),
+  ]);
+
+  let errors = errors(&dir.join("main.asm"));
+  assert_eq!(errors.len(), 1, "{:?}", errors);
+  match &errors[0] {
+    ErrorKind::IncludeCycle(chain) => {
+      let files: Vec<&str> = chain.split(" -> ").map(|file| file.rsplit('/').next().unwrap()).collect();
+      assert_eq!(files, vec!["a.asm", "b.asm", "a.asm"]);
+    },
+    kind => panic!("expected an include cycle, found {:?}", kind),
+  }
+}
+
+#[test]
+fn a_file_can_include_itself_only_once() {
+  let dir = fixture("self", &[("main.asm", "@1\n.include \"main.asm\"\n")]);
+
+  assert!(matches!(errors(&dir.join("main.asm"))[..], [ErrorKind::IncludeCycle(_)]));
+}
+
+#[test]
+fn listings_name_each_file_they_switch_to() {
+  let dir = fixture("listing", &[
+    ("main.asm", "@1\n.include \"sub/lib.asm\"\nD=A\n"),
+    ("sub/lib.asm", "@2\n"),
+  ]);
+
+  let main = dir.join("main.asm").display().to_string();
+  let lib = dir.join("sub/lib.asm").display().to_string();
+  let listing = listing(&assemble_file(&dir.join("main.asm")).unwrap());
+  let headers: Vec<&str> = listing.lines().map(str::trim).filter(|line| line.starts_with("==> ")).collect();
+
+  assert_eq!(headers, vec![
+    format!("==> {} <==", main),
+    format!("==> {} <==", lib),
+    format!("==> {} <==", main),
+  ]);
+}
+
+#[test]
+fn listings_of_one_file_have_no_headers() {
+  let dir = fixture("single", &[("main.asm", "@1\nD=A\n")]);
+  let listing = listing(&assemble_file(&dir.join("main.asm")).unwrap());
+
+  assert!(!listing.contains("==>"), "{}", listing);
+}
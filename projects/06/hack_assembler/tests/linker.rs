@@ -0,0 +1,97 @@
+use hack_assembler::{assemble_object, link, Diagnostics, ErrorKind, Object, ObjectWord, Options, Sources};
+
+fn object(source: &str) -> Object {
+  assemble_object(source, &Options::default()).expect("assembles")
+}
+
+// link object files given as their text, in order
+fn link_texts(objects: &[String]) -> Result<Vec<u16>, Vec<ErrorKind>> {
+  let mut sources = Sources::default();
+  for (i, text) in objects.iter().enumerate() {
+    sources.add(&format!("{}.o", i), text.clone(), None);
+  }
+
+  match link(sources, &Options::default()) {
+    Ok(program)      => Ok(program.words),
+    Err(diagnostics) => Err(diagnostics.iter().map(|diagnostic| diagnostic.kind.clone()).collect()),
+  }
+}
+
+fn link_sources(sources: &[&str]) -> Result<Vec<u16>, Vec<ErrorKind>> {
+  link_texts(&sources.iter().map(|source| object(source).to_text()).collect::<Vec<_>>())
+}
+
+const MAIN: &str = ".extern PUTC\n.export START\n(START)\n@x\nM=0\n@PUTC+1\n0;JMP\n@START+2\n";
+const LIB: &str = ".export PUTC\n@2\n(PUTC)\nD=A\n@y\n";
+
+#[test]
+fn objects_survive_a_write_and_read() {
+  let written = object(MAIN);
+  let mut diagnostics = Diagnostics::new(0);
+  let read = Object::parse(&written.to_text(), 0, &mut diagnostics);
+
+  assert!(!diagnostics.has_errors());
+  assert_eq!(read.words, written.words);
+  assert_eq!(read.exports.iter().map(|(name, address, _)| (name.as_str(), *address)).collect::<Vec<_>>(), vec![("START", 0)]);
+  assert_eq!(read.to_text(), written.to_text());
+}
+
+#[test]
+fn objects_record_what_the_linker_fills_in() {
+  assert_eq!(object(MAIN).words, vec![
+    ObjectWord::Variable(String::from("x"), 0),
+    ObjectWord::Absolute(0b1110101010001000),
+    ObjectWord::Extern(String::from("PUTC"), 1),
+    ObjectWord::Absolute(0b1110101010000111),
+    ObjectWord::Label(2),
+  ]);
+}
+
+#[test]
+fn linking_relocates_labels_plus_constants() {
+  // LIB goes first, so MAIN starts at 3 and its PUTC+1 is 2
+  assert_eq!(link_sources(&[LIB, MAIN]), Ok(vec![
+    2,
+    0b1110110000010000,
+    16,
+    17,
+    0b1110101010001000,
+    2,
+    0b1110101010000111,
+    5,
+  ]));
+}
+
+#[test]
+fn unresolved_externs_are_errors() {
+  assert_eq!(link_sources(&[MAIN]), Err(vec![ErrorKind::UnresolvedExtern(String::from("PUTC"))]));
+}
+
+// without `.extern`, a name is a variable and cannot pick up another object's label
+#[test]
+fn variables_cannot_share_a_name_with_an_export() {
+  assert_eq!(link_sources(&[LIB, "@PUTC\n"]), Err(vec![ErrorKind::VariableIsExported(String::from("PUTC"))]));
+}
+
+#[test]
+fn an_export_can_only_come_from_one_object() {
+  let errors = link_sources(&[LIB, MAIN, LIB]).expect_err("fails");
+
+  assert_eq!(errors, vec![ErrorKind::DuplicateExport(String::from("PUTC"))]);
+}
+
+#[test]
+fn label_arithmetic_must_stay_relocatable() {
+  let diagnostics = assemble_object("(L)\n@L*2\n", &Options::default()).expect_err("fails");
+  let errors: Vec<ErrorKind> = diagnostics.iter().map(|diagnostic| diagnostic.kind.clone()).collect();
+
+  assert_eq!(errors, vec![ErrorKind::NotRelocatable(String::from("L*2"))]);
+  assert!(assemble_object("(L)\n@L-1\n@1+L\n", &Options::default()).is_ok());
+}
+
+#[test]
+fn broken_object_files_are_rejected() {
+  let errors = link_texts(&[String::from("hack-object 1\nlabel x\n")]).expect_err("fails");
+
+  assert!(matches!(errors[..], [ErrorKind::MalformedObject(_)]), "{:?}", errors);
+}
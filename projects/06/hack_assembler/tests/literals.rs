@@ -0,0 +1,35 @@
+use hack_assembler::{assemble, ErrorKind, Options};
+
+fn words(source: &str) -> Vec<u16> {
+  assemble(source, &Options::default()).expect("assembles").words
+}
+
+fn errors(source: &str) -> Vec<ErrorKind> {
+  match assemble(source, &Options::default()) {
+    Ok(_)            => Vec::new(),
+    Err(diagnostics) => diagnostics.iter().map(|diagnostic| diagnostic.kind.clone()).collect(),
+  }
+}
+
+#[test]
+fn literals_in_every_radix() {
+  assert_eq!(words("@0x7fff\n@0b101\n@'A'\n@42\n"), vec![0x7fff, 5, 65, 42]);
+}
+
+#[test]
+fn prefixes_need_digits() {
+  let no_digits = |s: &str, radix: &str| ErrorKind::BadLiteral(String::from(s), format!("{} literal has no digits", radix));
+
+  assert_eq!(errors("@0x\n"), vec![no_digits("0x", "hex")]);
+  assert_eq!(errors("@0B\n"), vec![no_digits("0B", "binary")]);
+  assert_eq!(errors("@0b\n"), vec![no_digits("0b", "binary")]);
+}
+
+// `0b` reads as a binary prefix even where label `0:` is defined
+#[test]
+fn zero_b_is_never_a_label_reference() {
+  let no_digits = ErrorKind::BadLiteral(String::from("0b"), String::from("binary literal has no digits"));
+
+  assert_eq!(errors("0:\nD=0\n@0b\n0;JMP\n"), vec![no_digits]);
+  assert_eq!(words("0:\nD=0\n@00b\n0;JMP\n"), vec![0xea90, 0, 0xea87]);
+}
@@ -0,0 +1,136 @@
+use hack_assembler::run_language_server;
+
+const URI: &str = "file:///tmp/main.asm";
+
+const MAIN: &str = r#"(LOOP)\n    @LOOP\n    0;JMP\n.macro GOEND\n    @END\n    0;JMP\n(INNER)\n    @INNER\n.endm\n    GOEND\n(END)\n    @END\n    0;JMP\n"#;
+
+fn frame(body: &str) -> String {
+  format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn request(id: u32, method: &str, params: &str) -> String {
+  format!(r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{}}}"#, id, method, params)
+}
+
+fn open(text: &str) -> String {
+  let document = format!(r#"{{"uri":"{}","languageId":"hack","version":1,"text":"{}"}}"#, URI, text);
+  format!(r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{}}}}}"#, document)
+}
+
+// `extra` is any more params, such as `"newName":"TOP"`, with a leading comma
+fn at(line: u32, character: u32, extra: &str) -> String {
+  format!(r#"{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}}{}}}"#, URI, line, character, extra)
+}
+
+// run a whole session, from `initialize` to `exit`, and give back the
+// bodies of everything the server sent after initializing
+fn session(messages: &[String]) -> Vec<String> {
+  let mut input = frame(&request(0, "initialize", "{}"));
+  for message in messages {
+    input.push_str(&frame(message));
+  }
+  input.push_str(&frame(&request(99, "shutdown", "null")));
+  input.push_str(&frame(r#"{"jsonrpc":"2.0","method":"exit"}"#));
+
+  let mut output = Vec::new();
+  run_language_server(input.as_bytes(), &mut output).expect("exits cleanly");
+
+  let output = String::from_utf8(output).expect("utf-8");
+  let mut bodies = Vec::new();
+  let mut rest = output.as_str();
+  while let Some(header) = rest.strip_prefix("Content-Length: ") {
+    let (length, after) = header.split_once("\r\n\r\n").expect("a header");
+    let length: usize = length.parse().expect("a length");
+    bodies.push(String::from(&after[..length]));
+    rest = &after[length..];
+  }
+  assert!(rest.is_empty(), "unframed output {:?}", rest);
+
+  // drop the `initialize` and `shutdown` responses
+  assert_eq!(bodies.pop().as_deref(), Some(r#"{"jsonrpc":"2.0","id":99,"result":null}"#));
+  bodies.remove(0);
+  bodies
+}
+
+fn range(line: u32, start: u32, end: u32) -> String {
+  format!(r#"{{"start":{{"line":{},"character":{}}},"end":{{"line":{},"character":{}}}}}"#, line, start, line, end)
+}
+
+fn location(line: u32, start: u32, end: u32) -> String {
+  format!(r#"{{"uri":"{}","range":{}}}"#, URI, range(line, start, end))
+}
+
+fn edit(line: u32, start: u32, end: u32, text: &str) -> String {
+  format!(r#"{{"range":{},"newText":"{}"}}"#, range(line, start, end), text)
+}
+
+#[test]
+fn definition_finds_the_label() {
+  let replies = session(&[open(MAIN), request(1, "textDocument/definition", &at(1, 6, ""))]);
+
+  assert_eq!(replies[1], format!(r#"{{"jsonrpc":"2.0","id":1,"result":{}}}"#, location(0, 1, 5)));
+}
+
+#[test]
+fn references_include_uses_in_macro_bodies() {
+  let params = at(10, 2, r#","context":{"includeDeclaration":false}"#);
+  let replies = session(&[open(MAIN), request(1, "textDocument/references", &params)]);
+
+  let expected = [location(4, 5, 8), location(11, 5, 8)].join(",");
+  assert_eq!(replies[1], format!(r#"{{"jsonrpc":"2.0","id":1,"result":[{}]}}"#, expected));
+}
+
+#[test]
+fn hover_shows_the_address_and_the_word() {
+  let replies = session(&[open(MAIN), request(1, "textDocument/hover", &at(1, 6, ""))]);
+
+  let value = r#"label `LOOP`: ROM address 0\n\n```\n0      0000000000000000\n```"#;
+  assert_eq!(replies[1], format!(r#"{{"jsonrpc":"2.0","id":1,"result":{{"contents":{{"kind":"markdown","value":"{}"}}}}}}"#, value));
+}
+
+#[test]
+fn rename_reaches_into_macro_bodies() {
+  let replies = session(&[open(MAIN), request(1, "textDocument/rename", &at(11, 6, r#","newName":"FINISH""#))]);
+
+  let edits = [edit(4, 5, 8, "FINISH"), edit(10, 1, 4, "FINISH"), edit(11, 5, 8, "FINISH")].join(",");
+  assert_eq!(replies[1], format!(r#"{{"jsonrpc":"2.0","id":1,"result":{{"changes":{{"{}":[{}]}}}}}}"#, URI, edits));
+}
+
+#[test]
+fn rename_will_not_take_a_macro_label() {
+  let replies = session(&[open(MAIN), request(1, "textDocument/rename", &at(10, 2, r#","newName":"INNER""#))]);
+
+  assert!(replies[1].contains(r#""error":"#), "{}", replies[1]);
+  assert!(replies[1].contains("`INNER` is a label of a macro that uses `END`"), "{}", replies[1]);
+}
+
+#[test]
+fn json_escapes_are_read_and_written() {
+  // `(A)` is spelt with `\u` escapes, `@A/2` with `\/` and the emoji as a surrogate pair
+  let text = r#"\u0028A\u0029\n\t@A\/2\n.string s \"\ud83d\ude00\" junk\r\n"#;
+  let replies = session(&[open(text), request(1, "textDocument/definition", &at(1, 2, ""))]);
+
+  let message = "\"message\":\"bad literal `\\\"\u{1f600}\\\" junk`: expected a string in double quotes\"";
+  assert_eq!(replies[0].matches("\"message\"").count(), 1, "{}", replies[0]);
+  assert!(replies[0].contains(message), "{}", replies[0]);
+
+  // the emoji is two UTF-16 units, so the bad literal ends at 19, not 18
+  assert!(replies[0].contains(&range(2, 10, 19)), "{}", replies[0]);
+  assert_eq!(replies[1], format!(r#"{{"jsonrpc":"2.0","id":1,"result":{}}}"#, location(0, 1, 2)));
+}
+
+#[test]
+fn nested_and_broken_json() {
+  let nested = r#"{"a":[1,-2.5e3,{"b":[true,false,null,[[[]]],{}]}],"c":"é\\"}"#;
+  let replies = session(&[
+    request(1, "textDocument/hover", &format!(r#"{{"textDocument":{{"uri":"file:///none"}},"extra":{}}}"#, nested)),
+    String::from(r#"{"jsonrpc":"2.0","id":2,"#),
+    request(3, "bogus", "[]"),
+  ]);
+
+  assert_eq!(replies, vec![
+    String::from(r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32602,"message":"`file:///none` is not open"}}"#),
+    String::from(r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"expected a string"}}"#),
+    String::from(r#"{"jsonrpc":"2.0","id":3,"error":{"code":-32601,"message":"unknown method `bogus`"}}"#),
+  ]);
+}
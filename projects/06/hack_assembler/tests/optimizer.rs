@@ -0,0 +1,59 @@
+use hack_assembler::{assemble, Options};
+
+fn words(source: &str, optimize: bool) -> Vec<u16> {
+  let options = Options { optimize, ..Options::default() };
+  assemble(source, &options).expect("assembles").words
+}
+
+#[test]
+fn removes_dead_code() {
+  let source = "@1\nD=A\n@END\n0;JMP\n@7\nD=A\n(END)\n@END\n0;JMP\n";
+
+  assert_eq!(words(source, true), words("@1\nD=A\n(END)\n@END\n0;JMP\n", false));
+}
+
+// `T+3` is the `@7` the passes would otherwise drop as unreachable
+#[test]
+fn keeps_code_reached_through_a_label_offset() {
+  let source = "@T+3\n0;JMP\n(T)\n@1\nD=A\n0;JMP\n@7\nD=A\n(END)\n@END\n0;JMP\n";
+
+  assert_eq!(words(source, true), words(source, false));
+}
+
+#[test]
+fn keeps_code_reached_through_a_local_label_offset() {
+  let source = "(MAIN)\n@.t+2\n0;JMP\n(.t)\n@1\n0;JMP\n@7\nD=A\n(END)\n@END\n0;JMP\n";
+
+  assert_eq!(words(source, true), words(source, false));
+}
+
+// with no labels, the code after `0;JMP` is only reached by its address
+#[test]
+fn keeps_code_reached_through_a_numbered_address() {
+  let source = "@3\n0;JMP\n@7\nD=A\n@5\n0;JMP\n";
+
+  assert_eq!(words(source, true), words(source, false));
+}
+
+// the target is loaded before an instruction that leaves A alone
+#[test]
+fn keeps_code_reached_through_an_address_loaded_earlier() {
+  let source = "@6\nD=0\n0;JMP\n@7\nD=A\n@END\n(END)\n@END\n0;JMP\n";
+
+  assert_eq!(words(source, true), words(source, false));
+}
+
+#[test]
+fn keeps_code_reached_through_a_loaded_address() {
+  let source = "LOAD A, 4\n0;JMP\n@7\nD=A\n@7\nD=A\n(END)\n@END\n0;JMP\n";
+
+  assert_eq!(words(source, true), words(source, false));
+}
+
+// a return address kept in memory could point anywhere
+#[test]
+fn keeps_code_reached_through_an_address_in_memory() {
+  let source = "@R13\nA=M\n0;JMP\n@7\nD=A\n(END)\n@END\n0;JMP\n";
+
+  assert_eq!(words(source, true), words(source, false));
+}
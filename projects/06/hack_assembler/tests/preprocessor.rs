@@ -0,0 +1,58 @@
+use hack_assembler::{assemble, ErrorKind, Options, Severity};
+
+fn words(source: &str) -> Vec<u16> {
+  assemble(source, &Options::default()).expect("assembles").words
+}
+
+fn errors(source: &str) -> Vec<ErrorKind> {
+  match assemble(source, &Options::default()) {
+    Ok(_)            => Vec::new(),
+    Err(diagnostics) => diagnostics
+      .iter()
+      .filter(|diagnostic| diagnostic.severity == Severity::Error)
+      .map(|diagnostic| diagnostic.kind.clone())
+      .collect(),
+  }
+}
+
+// commas inside string and char literals do not split arguments
+#[test]
+fn macro_arguments_split_on_commas_outside_literals() {
+  let source = ".macro S name, text\n.string \\name \\text\n.endm\nS hello, \"a, b\"\n@hello\n";
+  assert_eq!(words(source), words(".string hello \"a, b\"\n@hello\n"));
+
+  let source = ".macro C value\n@\\value\nD=A\n.endm\nC ','\n";
+  assert_eq!(words(source), words("@44\nD=A\n"));
+}
+
+#[test]
+fn macro_arity_counts_empty_arguments() {
+  let source = ".macro M a, b\n@\\a\n@\\b\n.endm\nM 3,,\n";
+
+  assert_eq!(errors(source), vec![ErrorKind::MacroArity(String::from("M"), 2, 3)]);
+}
+
+#[test]
+fn include_takes_a_quoted_name() {
+  let malformed = ErrorKind::MalformedInclude(String::from("expected a quoted file name, e.g. `.include \"lib.asm\"`"));
+
+  assert_eq!(errors(".include lib.asm\n"), vec![malformed.clone()]);
+  assert_eq!(errors(".include \"lib.asm\" \"x\"\n"), vec![malformed]);
+}
+
+#[test]
+fn conditions_read_the_whole_argument() {
+  assert_eq!(words(".if 2 * (1 - 1)\n@1\n.else\n@2\n.endif\n"), vec![2]);
+  assert_eq!(
+    errors(".ifdef\n.endif\n"),
+    vec![ErrorKind::MalformedConditional(String::from("`.ifdef` needs a condition"))],
+  );
+}
+
+#[test]
+fn macro_bodies_reject_conditionals_and_includes() {
+  let source = ".macro M\n.ifdef X\n@1\n.endif\n.include \"lib.asm\"\n.endm\nM\n";
+  let rejected = |name: &str| ErrorKind::MalformedMacro(format!("`{}` is not allowed inside a macro", name));
+
+  assert_eq!(errors(source), vec![rejected(".ifdef"), rejected(".endif"), rejected(".include")]);
+}
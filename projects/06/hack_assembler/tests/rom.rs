@@ -0,0 +1,21 @@
+use hack_assembler::{assemble, Options};
+
+// 32767 words, so `@E` takes the last ROM address
+fn full(target: &str) -> String {
+  format!("{}@{}\n", "D=0\n".repeat(32766), target)
+}
+
+#[test]
+fn label_at_the_last_address_assembles() {
+  let program = assemble(&format!("{}(E)\n", full("E")), &Options::default()).expect("assembles");
+
+  assert_eq!(program.words.last(), Some(&32767));
+}
+
+// 32768 would encode as a C-instruction
+#[test]
+fn label_past_the_last_address_is_an_error() {
+  let source = format!("D=0\n{}(E)\n", full("E"));
+
+  assert!(assemble(&source, &Options::default()).is_err());
+}
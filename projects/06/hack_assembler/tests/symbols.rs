@@ -0,0 +1,72 @@
+use std::fs;
+use std::process;
+
+use hack_assembler::{assemble, ErrorKind, Options, Severity};
+
+fn errors(source: &str) -> Vec<ErrorKind> {
+  match assemble(source, &Options::default()) {
+    Ok(_)            => Vec::new(),
+    Err(diagnostics) => diagnostics
+      .iter()
+      .filter(|diagnostic| diagnostic.severity == Severity::Error)
+      .map(|diagnostic| diagnostic.kind.clone())
+      .collect(),
+  }
+}
+
+#[test]
+fn label_cannot_redefine_a_predefined_symbol() {
+  assert_eq!(errors("@R0\n(R0)\n@R0\n"), vec![ErrorKind::DuplicateSymbol(String::from("R0"), None)]);
+  assert_eq!(errors("(SCREEN)\n0;JMP\n"), vec![ErrorKind::DuplicateSymbol(String::from("SCREEN"), None)]);
+}
+
+// labels and data blocks share one namespace with the predefined symbols
+#[test]
+fn data_cannot_redefine_a_predefined_symbol() {
+  assert_eq!(errors(".word SCREEN 1\n"), vec![ErrorKind::DuplicateSymbol(String::from("SCREEN"), None)]);
+}
+
+// a name starting with `.` can only be a local label, never a variable
+#[test]
+fn undefined_local_label_is_an_error() {
+  let local = |name: &str| ErrorKind::UndefinedLocalLabel(String::from(name));
+
+  assert_eq!(errors("@.nope\n(MAIN)\n@.nope\n"), vec![local(".nope"), local("MAIN.nope")]);
+  assert_eq!(errors("(MAIN)\n(.ok)\n@.ok\n@MAIN.ok\n"), Vec::new());
+}
+
+#[test]
+fn symbol_files_name_the_file_of_each_first_use() {
+  let dir = std::env::temp_dir().join(format!("hack_assembler_symbols_{}", process::id()));
+  fs::create_dir_all(&dir).unwrap();
+  fs::write(dir.join("lib.asm"), "@x\n@END\n").unwrap();
+
+  let main = dir.join("main.asm").display().to_string();
+  let lib = dir.join("lib.asm").display().to_string();
+  let options = Options {
+    file_name: main.clone(),
+    ..Options::default()
+  };
+  let program = assemble("(END)\n.include \"lib.asm\"\n@x\n", &options).expect("assembles");
+  let rows: Vec<String> = program.symbols
+    .to_sym(&program.sources)
+    .lines()
+    .filter(|row| !row.starts_with("predefined"))
+    .map(|row| row.split_whitespace().collect::<Vec<_>>().join(" "))
+    .collect();
+
+  assert_eq!(rows[1..], [format!("label 0 END {}:2", lib), format!("variable 16 x {}:1", lib)]);
+}
+
+#[test]
+fn json_symbol_files_escape_names() {
+  let options = Options {
+    defines: vec![(String::from("A\"B\\"), 1)],
+    ..Options::default()
+  };
+  let program = assemble("@x\n", &options).expect("assembles");
+  let json = program.symbols.to_json(&program.sources);
+
+  assert!(json.contains(r#"{"name":"A\"B\\","kind":"defined","address":1,"first_use":null}"#), "{}", json);
+  assert!(json.contains(r#"{"name":"x","kind":"variable","address":16,"first_use":"<input>:1"}"#), "{}", json);
+}
@@ -0,0 +1,69 @@
+use hack_assembler::{assemble, tokenize, ErrorKind, LineKind, Options, Span, SyntaxTree, TokenKind};
+
+fn spans(source: &str) -> Vec<(ErrorKind, Span)> {
+  match assemble(source, &Options::default()) {
+    Ok(_)            => Vec::new(),
+    Err(diagnostics) => diagnostics.iter().map(|diagnostic| (diagnostic.kind.clone(), diagnostic.span)).collect(),
+  }
+}
+
+const MIXED: &str = "\u{feff}// header\r\n  @x  // y\r\n(LOOP)\n\tD=M ; JGT\r.string s \"a, b\"\n\n";
+
+#[test]
+fn tokens_cover_every_byte() {
+  let tokens = tokenize(MIXED);
+
+  assert_eq!(tokens.iter().map(|token| token.text).collect::<String>(), MIXED);
+  for pair in tokens.windows(2) {
+    assert_eq!(pair[0].span.end, pair[1].span.start);
+  }
+  assert_eq!(tokens[0].kind, TokenKind::Bom);
+  assert_eq!(tokens[2].kind, TokenKind::Newline);
+  assert_eq!(tokens[2].text, "\r\n");
+}
+
+#[test]
+fn syntax_tree_writes_the_source_back() {
+  for source in [MIXED, "", "\n", "@1", "\r\n\r\n", include_str!("../pongL.asm")] {
+    let tree = SyntaxTree::parse(source);
+    let bom = tree.bom.as_ref().map_or("", |bom| bom.text);
+    let lines: String = tree.lines.iter().map(|line| line.to_string()).collect();
+
+    assert_eq!(format!("{}{}", bom, lines), source);
+  }
+}
+
+#[test]
+fn syntax_lines_know_their_code() {
+  let tree = SyntaxTree::parse(MIXED);
+  let lines: Vec<(LineKind, &str, Span)> = tree.lines.iter().map(|line| (line.kind, line.code(), line.span())).collect();
+
+  assert_eq!(lines, vec![
+    (LineKind::Comment, "",                  Span::new(1, 1, 0)),
+    (LineKind::Code,    "@x",                Span::new(2, 3, 2)),
+    (LineKind::Label,   "(LOOP)",            Span::new(3, 1, 6)),
+    (LineKind::Code,    "D=M ; JGT",         Span::new(4, 2, 9)),
+    (LineKind::Code,    ".string s \"a, b\"", Span::new(5, 1, 16)),
+    (LineKind::Blank,   "",                  Span::new(6, 1, 0)),
+    (LineKind::Blank,   "",                  Span::new(7, 1, 0)),
+  ]);
+  assert!(tree.is_crlf());
+}
+
+// a byte order mark takes no column, and every line break ends a line
+#[test]
+fn diagnostics_point_past_bom_and_crlf() {
+  let unknown = |line| (ErrorKind::UnknownComp(String::from("Q")), Span::new(line, 3, 1));
+
+  assert_eq!(spans("\u{feff}D=Q\r\n"), vec![unknown(1)]);
+  assert_eq!(spans("@1\r\nD=Q\r\n"), vec![unknown(2)]);
+  assert_eq!(spans("@1\r\r\nD=Q"), vec![unknown(3)]);
+  assert_eq!(spans("\u{feff}@1\n\r\nD=Q"), vec![unknown(3)]);
+}
+
+#[test]
+fn diagnostic_columns_count_chars() {
+  let error = ErrorKind::BadLiteral(String::from("\"\u{e9}\u{20ac}\" junk"), String::from("expected a string in double quotes"));
+
+  assert_eq!(spans("\u{feff}.string s \"\u{e9}\u{20ac}\" junk\n"), vec![(error, Span::new(1, 11, 9))]);
+}